@@ -12,6 +12,8 @@ use crate::clients::Client as TraitClient;
 
 use crate::clients::client_chen::prelude::*;
 use crate::clients::client_chen::{CommandHandler, FragmentsHandler, PacketsReceiver, Router, Sending};
+use crate::id_allocator::IdAllocator;
+use crate::encoding::Encoding;
 
 #[derive(Clone)]
 pub(crate) struct ClientChen {
@@ -47,7 +49,9 @@ impl TraitClient for ClientChen {
             // Status
             status: NodeStatus {
                 flood_id: 0, // Initial value to be 0 for every new client
-                session_id: (id as u64) << 56, // Put the id of the client in the first 8 bits
+                session_id: 0,
+                ids: IdAllocator::new(id), // Puts the id of the client in the first 8 bits of every id
+                encoding: Encoding::default(),
             },
 
             // Communication-related data
@@ -55,6 +59,7 @@ impl TraitClient for ClientChen {
                 connected_nodes_ids: packet_send.keys().cloned().collect(),
                 registered_communication_servers: HashMap::new(),
                 registered_content_servers: HashSet::new(),
+                server_capabilities: HashMap::new(),
                 routing_table: HashMap::new(),
             },
 
@@ -75,6 +80,9 @@ impl TraitClient for ClientChen {
                 output_packet_disk: HashMap::new(),  //if at the end of the implementation still doesn't need then delete
                 packets_status: HashMap::new(),
                 message_chat: HashMap::new(),
+                message_status: HashMap::new(),
+                history_cursors: HashMap::new(),
+                history_pages: HashMap::new(),
                 file_storage: HashMap::new(),
             },
 
@@ -121,6 +129,8 @@ pub(crate) struct NodeMetadata {
 pub(crate) struct NodeStatus {
     pub(crate) flood_id: FloodId,
     pub(crate) session_id: SessionId,
+    pub(crate) ids: IdAllocator,
+    pub(crate) encoding: Encoding, // Encoding of the queries sent to the servers
 }
 
 // Communication-related information
@@ -129,6 +139,7 @@ pub(crate) struct CommunicationInfo {
     pub(crate) connected_nodes_ids: HashSet<NodeId>,
     pub(crate) registered_communication_servers: HashMap<ServerId, Vec<ClientId>>, // Servers registered by the client with respective registered clients
     pub(crate) registered_content_servers: HashSet<ServerId>,
+    pub(crate) server_capabilities: HashMap<ServerId, ServerCapabilities>, // Handshakes received from the servers
     pub(crate) routing_table: HashMap<NodeId, HashMap<Vec<NodeId>, UsingTimes>>, // Routing information per protocol
 }
 
@@ -151,6 +162,9 @@ pub struct NodeStorage {
     pub(crate) output_packet_disk: HashMap<(SessionId, FragmentIndex), Packet>,         // Storage for sent packets
     pub(crate) packets_status: HashMap<(SessionId, FragmentIndex), PacketStatus>,       // Map every packet with the status of sending
     pub(crate) message_chat: HashMap<ClientId, Vec<(Speaker, Message)>>,               // Chat messages with other clients
+    pub(crate) message_status: HashMap<MessageId, (ClientId, MessageStatus)>,          // Status of the messages sent to other clients
+    pub(crate) history_cursors: HashMap<(ServerId, ClientId), u64>,                    // Next history message to ask to each server, for each client
+    pub(crate) history_pages: HashMap<(ServerId, ClientId), Vec<HistoryEntry>>,        // History pages received until the last one arrives
     pub(crate) file_storage: HashMap<ServerId, File>,                                  // Files received from media servers
 }
 
@@ -43,7 +43,6 @@ pub trait CommunicationTools{
 
 pub trait PacketCreator{
     ///creating fragment packet
-    fn divide_string_into_slices(&mut self, string: String, max_slice_length: usize) -> Vec<String>;
     fn msg_to_fragments<T: Serialize>(&mut self, msg: T, destination_id: NodeId) -> Option<Vec<Packet>>;
     ///creating ack packet
     fn create_ack_packet_from_receiving_packet(&mut self, packet: Packet) -> Packet;
@@ -96,9 +95,11 @@ pub trait FragmentsHandler:PacketsReceiver{ //message fragments
        THE MESSAGE THAT THE SERVER SENDS TO THE CLIENTS.
      */
     fn register_client(&mut self, initiator_id: NodeId);
+    fn update_message_status(&mut self, message_id: MessageId, status: MessageStatus);
+    fn handle_history(&mut self, initiator_id: NodeId, client_id: ClientId, entries: Vec<HistoryEntry>, next: Option<u64>);
 
     ///principal methods
-    fn reassemble_fragments_in_buffer(&mut self) -> Result<Response, String>;
+    fn reassemble_fragments_in_buffer(&mut self, session_id: SessionId) -> Result<Response, String>;
 
 }
 
@@ -112,6 +113,7 @@ pub trait ServerQuery{
     fn ask_server_type(&mut self, server_id: ServerId);
     fn ask_list_clients(&mut self, server_id: ServerId);
     fn send_message_to_client(&mut self, server_id: ServerId, client_id: ClientId, message: Message);
+    fn ask_history(&mut self, server_id: ServerId, client_id: ClientId);   //only the messages after the last history received
     fn ask_list_files(&mut self, server_id: ServerId);  //all the files that a server has, so not a specific file_ref (or file_index)
     fn ask_file(&mut self, server_id: ServerId, file_ref: u8);
     fn ask_media(&mut self, server_id: ServerId, media_ref: String);  //string is the reference found in the files
@@ -41,6 +41,12 @@ impl CommandHandler for ClientChen{
                     .collect();
                 self.send_events(ClientEvent::KnownServers(servers));
             }
+            ClientCommand::SendMessageTo(server_id, client_id, message) => {
+                self.send_message_to_client(server_id, client_id, message);
+            }
+            ClientCommand::SetEncoding(encoding) => {
+                self.status.encoding = encoding;
+            }
             _=>{}
         }
     }
@@ -8,7 +8,7 @@ impl FloodingPacketsHandler for ClientChen {
         self.storage.input_packet_disk.insert((packet.session_id, 0), packet);
 
         // Prepare the flood response.
-        self.status.session_id += 1;
+        self.status.session_id = self.status.ids.next_session_id();
         request.path_trace.push((self.metadata.node_id, self.metadata.node_type));
         let response = request.generate_response(self.status.session_id);
 
@@ -1,7 +1,9 @@
 use serde::de::DeserializeOwned;
-use crate::clients::client_chen::{ClientChen, FragmentsHandler, PacketCreator, PacketsReceiver, Sending, SpecificInfo};
+use crate::clients::client_chen::{ClientChen, FragmentsHandler, PacketCreator, PacketsReceiver, Sending, ServerQuery, SpecificInfo};
 use crate::clients::client_chen::prelude::*;
 use crate::clients::client_chen::general_client_traits::*;
+use crate::fragmentation::Reassembler;
+use crate::encoding::Encoding;
 impl FragmentsHandler for ClientChen{
     fn handle_fragment(&mut self, msg_packet: Packet, fragment: Fragment) {
         self.decreasing_using_times_when_receiving_packet(&msg_packet);
@@ -58,7 +60,7 @@ impl FragmentsHandler for ClientChen{
                         let initiator_id = self.get_packet_destination(&first_packet);
 
                         // Reassemble fragments and process the message
-                        if let Ok(message) = self.reassemble_fragments_in_buffer() {
+                        if let Ok(message) = self.reassemble_fragments_in_buffer(session_id) {
                             self.process_message(initiator_id, message);
                         } else {
                             eprintln!("Failed to reassemble fragments for session: {:?}", session_id);
@@ -72,19 +74,50 @@ impl FragmentsHandler for ClientChen{
     fn process_message(&mut self, initiator_id: NodeId, message: Response) {
         match message {
             Response::ServerType(server_type) => self.update_topology_entry_for_server(initiator_id, server_type),
-            Response::ClientRegistered => self.register_client(initiator_id),
-            Response::MessageFrom(client_id, message) => {
+            Response::Capabilities(capabilities) => {
+                if capabilities.protocol_version != PROTOCOL_VERSION {
+                    warn!("Server {} speaks protocol version {}, ours is {}", initiator_id, capabilities.protocol_version, PROTOCOL_VERSION);
+                }
+                self.update_topology_entry_for_server(initiator_id, capabilities.server_type);
+                self.communication.server_capabilities.insert(initiator_id, capabilities);
+            }
+            Response::ClientRegistered => {
+                self.register_client(initiator_id);
+                //the list of clients tells whose history to ask
+                self.ask_list_clients(initiator_id);
+            }
+            Response::ClientUnregistered => {
+                self.communication.registered_communication_servers.remove(&initiator_id);
+            }
+            Response::MessageFrom(client_id, _message_id, message) => {
+                //no read receipt: this client has no chat view telling when a message is actually read
                 self.storage
                     .message_chat
                     .entry(client_id)
                     .or_insert_with(Vec::new)
                     .push((Speaker::HimOrHer, message));
             }
+            Response::MessageDelivered(client_id, message_id) => {
+                debug!("Message to {} delivered", client_id);
+                self.update_message_status(message_id, MessageStatus::Delivered);
+            }
+            Response::MessageQueued(client_id, message_id) => {
+                debug!("Client {} is offline, message queued on the server", client_id);
+                self.update_message_status(message_id, MessageStatus::Queued);
+            }
+            Response::MessageRead(client_id, message_id) => {
+                debug!("Message read by {}", client_id);
+                self.update_message_status(message_id, MessageStatus::Read);
+            }
             Response::ListClients(list_users) => {
+                for client_id in list_users.iter().filter(|&&id| id != self.metadata.node_id) {
+                    self.ask_history(initiator_id, *client_id);
+                }
                 self.communication
                     .registered_communication_servers
                     .insert(initiator_id, list_users);
             }
+            Response::History(client_id, entries, next) => self.handle_history(initiator_id, client_id, entries, next),
             Response::ListFiles(_) | Response::File(_) | Response::Media(_) => {
                 // Placeholder for file/media handling
             }
@@ -113,38 +146,95 @@ impl FragmentsHandler for ClientChen{
         }
     }
 
-    fn reassemble_fragments_in_buffer(&mut self) -> Result<Response, String> {
-        let mut keys: Vec<(SessionId, FragmentIndex)> = self
+    fn update_message_status(&mut self, message_id: MessageId, status: MessageStatus) {
+        //a status never goes back, a late delivery doesn't hide that the message was read
+        if let Some((_, current)) = self.storage.message_status.get_mut(&message_id) {
+            *current = (*current).max(status);
+        }
+    }
+
+    fn handle_history(&mut self, initiator_id: NodeId, client_id: ClientId, entries: Vec<HistoryEntry>, next: Option<u64>) {
+        self.storage
+            .history_pages
+            .entry((initiator_id, client_id))
+            .or_default()
+            .extend(entries);
+
+        //the pages are asked one after the other, the chat is updated with the last one
+        if let Some(since) = next {
+            self.send_query(initiator_id, Query::AskHistory(client_id, since));
+            return;
+        }
+        let history = self.storage.history_pages.remove(&(initiator_id, client_id)).unwrap_or_default();
+        if let Some(last) = history.last() {
+            self.storage.history_cursors.insert((initiator_id, client_id), last.seq + 1);
+        }
+
+        //the messages we already have and are in the history take their place in it,
+        //the others (older ones, or from other servers) stay before it
+        let my_id = self.metadata.node_id;
+        let mut history: Vec<(Speaker, Message)> = history
+            .into_iter()
+            .map(|entry| {
+                let speaker = if entry.from == my_id { Speaker::Me } else { Speaker::HimOrHer };
+                (speaker, entry.message)
+            })
+            .collect();
+        let chat = self.storage.message_chat.entry(client_id).or_default();
+        let mut not_matched: Vec<bool> = vec![true; history.len()];
+        let mut merged: Vec<(Speaker, Message)> = Vec::new();
+        for (speaker, message) in chat.drain(..) {
+            let found = (0..history.len()).find(|&i| {
+                not_matched[i]
+                    && history[i].1 == message
+                    && matches!((&speaker, &history[i].0), (Speaker::Me, Speaker::Me) | (Speaker::HimOrHer, Speaker::HimOrHer))
+            });
+            match found {
+                Some(i) => not_matched[i] = false,
+                None => merged.push((speaker, message)),
+            }
+        }
+        merged.append(&mut history);
+        *chat = merged;
+    }
+
+    fn reassemble_fragments_in_buffer(&mut self, session_id: SessionId) -> Result<Response, String> {
+        let keys: Vec<(SessionId, FragmentIndex)> = self
             .storage
             .fragment_assembling_buffer
             .keys()
+            .filter(|(session, _)| *session == session_id)
             .cloned()
             .collect();
 
-        // Sort the keys by fragment index
-        keys.sort_by_key(|key| key.1);
-
-        let mut serialized_entire_msg = String::new();
+        let total_n_fragments = self
+            .get_total_n_fragments(session_id)
+            .ok_or_else(|| format!("No fragments for session: {:?}", session_id))?;
+        let mut reassembler = Reassembler::new(total_n_fragments)
+            .ok_or_else(|| format!("Too many fragments for session: {:?}", session_id))?;
 
+        //the fragments of the session leave the buffer, whatever the result
         for key in keys {
-            if let Some(associated_packet) = self.storage.fragment_assembling_buffer.get(&key) {
+            if let Some(associated_packet) = self.storage.fragment_assembling_buffer.remove(&key) {
                 match &associated_packet.pack_type {
                     PacketType::MsgFragment(fragment) => {
-                        if let Ok(data_str) = std::str::from_utf8(&fragment.data) {
-                            serialized_entire_msg.push_str(data_str);
-                        } else {
-                            return Err(format!("Invalid UTF-8 sequence in fragment for key: {:?}", key));
+                        if !reassembler.insert(fragment) {
+                            return Err(format!("Invalid fragment for key: {:?}", key));
                         }
                     }
                     _ => {
                         return Err(format!("Unexpected packet type for key: {:?}", key));
                     }
                 }
-            } else {
-                return Err(format!("Missing packet for key: {:?}", key));
             }
         }
 
-        serde_json::from_str(&serialized_entire_msg).map_err(|e| format!("Failed to deserialize message: {}", e))
+        let serialized_entire_msg = reassembler
+            .take_if_complete()
+            .ok_or_else(|| format!("Missing fragments for session: {:?}", session_id))?;
+
+        Encoding::decode(&serialized_entire_msg)
+            .map(|(_, message)| message)
+            .map_err(|e| format!("Failed to deserialize message: {}", e))
     }
 }
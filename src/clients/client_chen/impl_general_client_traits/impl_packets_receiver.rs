@@ -6,10 +6,7 @@ impl PacketsReceiver for ClientChen {
         //insert the packets into the input disk
         self.decreasing_using_times_when_receiving_packet(&packet);
         self.storage.input_packet_disk.insert((self.status.session_id, match packet.clone().pack_type {
-            PacketType::MsgFragment(fragment) => {
-                self.storage.fragment_assembling_buffer.insert((self.status.session_id, fragment.fragment_index), packet.clone());
-                fragment.fragment_index
-            },
+            PacketType::MsgFragment(fragment) => fragment.fragment_index,
             _ => 0,
         }), packet.clone());
 
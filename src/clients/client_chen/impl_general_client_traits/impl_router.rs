@@ -6,8 +6,8 @@ impl Router for ClientChen {
     ///main method of for discovering the routing
     fn do_flooding(&mut self) {
         // New ids for the flood and new session because of the flood response packet
-        self.status.flood_id += 1;
-        self.status.session_id += 1;
+        self.status.flood_id = self.status.ids.next_flood_id();
+        self.status.session_id = self.status.ids.next_session_id();
 
         self.communication.routing_table.clear();
         self.network_info.topology.clear();
@@ -26,6 +26,13 @@ impl Sending for ClientChen {
         self.communication_tools.controller_send.send(client_event).expect("Client event not successfully sent");
     }
     fn send_query(&mut self, server_id: ServerId, query: Query) {
+        // Servers that didn't send their capabilities are asked everything
+        if let Some(capabilities) = self.communication.server_capabilities.get(&server_id) {
+            if !capabilities.supports(&query) {
+                warn!("Server {} doesn't support {}, query not sent", server_id, query.name());
+                return;
+            }
+        }
         if let Some(messages) = self.msg_to_fragments(query.clone(), server_id) {
             for message in messages {
                 // if you send the messages, it automatically updates the buffers and status and packet disk
@@ -30,7 +30,16 @@ impl ServerQuery for ClientChen{
 
     fn send_message_to_client(&mut self, server_id: ServerId, client_id: ClientId, message: Message) {
         if self.get_discovered_servers_from_topology().contains(&server_id){
-            self.send_query(server_id, Query::SendMessageTo(client_id, message));
+            let message_id = self.status.ids.next_message_id();
+            self.storage.message_status.insert(message_id, (client_id, MessageStatus::Sent));
+            self.send_query(server_id, Query::SendMessageTo(client_id, message_id, message));
+        }
+    }
+
+    fn ask_history(&mut self, server_id: ServerId, client_id: ClientId) {
+        if self.get_discovered_servers_from_topology().contains(&server_id) {
+            let since = self.storage.history_cursors.get(&(server_id, client_id)).copied().unwrap_or(0);
+            self.send_query(server_id, Query::AskHistory(client_id, since));
         }
     }
 
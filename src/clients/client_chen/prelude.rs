@@ -23,11 +23,16 @@ pub use crate::general_use::{ClientId,
                          ClientCommand,
                          ClientEvent,
                          Message,
+                         MessageId,
+                         MessageStatus,
+                         HistoryEntry,
                          NotSentType,
                          PacketStatus,
                          Query,
                          Response,
                          ServerType,
+                         ServerCapabilities,
+                         PROTOCOL_VERSION,
                          Speaker,
                          File,
                          UsingTimes,
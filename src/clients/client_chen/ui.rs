@@ -134,6 +134,7 @@ pub struct DisplayData {
     registered_content_servers: HashSet<ServerId>,
     routing_table: HashMap<NodeId, Vec<Vec<NodeId>>>,
     message_chat: HashMap<ClientId, Vec<(Speaker, Message)>>,
+    message_status: HashMap<MessageId, (ClientId, MessageStatus)>,
 }
 
 
@@ -193,6 +194,7 @@ impl Monitoring for ClientChen{
                             registered_content_servers: self.communication.registered_content_servers.clone(),
                             routing_table: transformed_routing_table,
                             message_chat: self.storage.message_chat.clone(),
+                            message_status: self.storage.message_status.clone(),
                         };
 
                         // Serialize the DisplayData to MessagePack binary
@@ -8,7 +8,7 @@ use wg_2024::{
     network::NodeId,
     packet::NodeType
 };
-use crate::general_use::ServerType;
+use crate::general_use::{MessageStatus, RoomName, ServerType};
 use super::{ChatClientDanylo, Node};
 
 #[derive(PartialEq)]
@@ -25,12 +25,16 @@ enum Menu {
 
     ChooseUser,
     SendMessageTo(NodeId),
+
+    Rooms,
+    Room(RoomName),
 }
 
 #[derive(PartialEq)]
 enum RequestType {
     AskType,
     RegisterClient,
+    UnregisterClient,
     AskListClients,
 }
 
@@ -40,7 +44,9 @@ pub struct ChatGUI<'a> {
     current_server: NodeId,
     current_message: Option<String>,
     current_message_status: Option<String>,
+    success_status: String,
     discovery_result: String,
+    room_name: String,
     wait_response: bool
 }
 
@@ -59,10 +65,17 @@ impl App for ChatGUI<'_>  {
 
                 Menu::SendRequest(RequestType::AskListClients) => self.ask_clients_list(ui),
                 Menu::SendRequest(RequestType::RegisterClient) => self.register_client(ui),
+                Menu::SendRequest(RequestType::UnregisterClient) => self.unregister_client(ui),
                 Menu::SendRequest(RequestType::AskType) => self.ask_type(ui),
 
                 Menu::ChooseUser => self.choose_user(ui),
                 Menu::SendMessageTo(id) => self.send_message(ui, id),
+
+                Menu::Rooms => self.rooms(ui),
+                Menu::Room(ref room) => {
+                    let room = room.clone();
+                    self.room(ui, room)
+                }
             }
         });
     }
@@ -76,7 +89,9 @@ impl <'a> ChatGUI<'a> {
             current_server: 0,
             current_message: Some(String::new()),
             current_message_status: None,
+            success_status: String::new(),
             discovery_result: String::new(),
+            room_name: String::new(),
             wait_response: false,
         }
     }
@@ -114,11 +129,14 @@ impl <'a> ChatGUI<'a> {
                 ui.label("No messages yet.");
                 ui.separator();
             } else {
-                for (sender, message) in &self.client.inbox {
-                    ui.label(format!("From client {}:\n{}", sender, message));
+                for (sender, message_id, message) in &self.client.inbox {
+                    let ticks = if self.client.unread.contains_key(message_id) { "(new)" } else { "✔✔" };
+                    ui.label(format!("From client {}: {}\n{}", sender, ticks, message));
                     ui.separator();
                 }
             }
+            // Shown once as new, then the senders learn that we read them.
+            self.client.mark_inbox_as_read();
 
             if ui.button("Back").clicked() {
                 self.current_menu = Menu::Main;
@@ -177,6 +195,12 @@ impl <'a> ChatGUI<'a> {
             if ui.button("Send message").clicked() {
                 self.current_menu = Menu::ChooseUser;
             }
+            if ui.button("Rooms").clicked() {
+                self.current_menu = Menu::Rooms;
+            }
+            if ui.button("Unregister").clicked() {
+                self.current_menu = Menu::SendRequest(RequestType::UnregisterClient);
+            }
         } else {
             if ui.button("Register").clicked() {
                 self.current_menu = Menu::SendRequest(RequestType::RegisterClient);
@@ -238,6 +262,11 @@ impl <'a> ChatGUI<'a> {
         ui.heading(format!("Send message to client {}", recipient));
         ui.separator();
 
+        for (_, _, message, status) in self.client.sent_messages.iter().filter(|(to, _, _, _)| *to == recipient) {
+            ui.label(format!("You: {} {}", message, Self::ticks(*status)));
+        }
+        ui.separator();
+
         let message = self.current_message.as_mut().unwrap();
 
         ui.horizontal(|ui| {
@@ -247,7 +276,7 @@ impl <'a> ChatGUI<'a> {
 
         while self.wait_response {
             if self.client.response_received {
-                self.current_message_status = Some("Message delivered successfully!".to_string());
+                self.current_message_status = Some(self.success_status.clone());
                 self.wait_response = false;
             }
             if let Some(error) = &self.client.external_error {
@@ -267,12 +296,25 @@ impl <'a> ChatGUI<'a> {
                 self.current_message_status = Some("Message cannot be empty.".to_string());
             } else {
                 match self.client.send_message_to(recipient, message.trim().to_string(), self.current_server) {
-                    Ok(_) => self.wait_response = true,
+                    Ok(_) => {
+                        self.wait_response = true;
+                        self.success_status = "Message delivered successfully!".to_string();
+                    }
                     Err(error) => self.current_message_status = Some(format!("Failed to send message: {}", error)),
                 };
             }
         }
 
+        if ui.button("Load history").clicked() {
+            match self.client.request_history(recipient, self.current_server) {
+                Ok(_) => {
+                    self.wait_response = true;
+                    self.success_status = "History loaded, missed messages are in the inbox.".to_string();
+                }
+                Err(error) => self.current_message_status = Some(format!("Failed to load history: {}", error)),
+            };
+        }
+
         ui.separator();
         if ui.button("Back").clicked() {
             self.current_message = Some(String::new());
@@ -281,6 +323,165 @@ impl <'a> ChatGUI<'a> {
         }
     }
 
+    fn rooms(&mut self, ui: &mut Ui) {
+        ui.heading(format!("Rooms on server {}", self.current_server));
+        ui.separator();
+
+        while self.wait_response {
+            if self.client.response_received {
+                self.current_message_status = None;
+                self.wait_response = false;
+            }
+            if let Some(error) = &self.client.external_error {
+                self.current_message_status = Some(format!("Request failed: {}", error));
+                self.wait_response = false;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        if ui.button("Refresh list").clicked() {
+            match self.client.request_rooms_list(self.current_server) {
+                Ok(_) => self.wait_response = true,
+                Err(error) => self.current_message_status = Some(format!("Failed to get rooms list: {}", error)),
+            }
+        }
+
+        let rooms = self.client.rooms.get(&self.current_server).cloned().unwrap_or_default();
+        if rooms.is_empty() {
+            ui.label("No rooms found.");
+        } else {
+            for room in rooms {
+                let joined = self.client.room_inbox.contains_key(&(self.current_server, room.clone()));
+                ui.horizontal(|ui| {
+                    ui.label(&room);
+                    if joined {
+                        if ui.button("Open").clicked() {
+                            self.current_menu = Menu::Room(room.clone());
+                        }
+                    } else if ui.button("Join").clicked() {
+                        match self.client.request_to_join_room(room.clone(), self.current_server) {
+                            Ok(_) => self.wait_response = true,
+                            Err(error) => self.current_message_status = Some(format!("Failed to join room: {}", error)),
+                        }
+                    }
+                });
+            }
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("New room: ");
+            ui.text_edit_singleline(&mut self.room_name);
+        });
+        if ui.button("Create").clicked() {
+            if self.room_name.trim().is_empty() {
+                self.current_message_status = Some("Room name cannot be empty.".to_string());
+            } else {
+                match self.client.request_to_create_room(self.room_name.trim().to_string(), self.current_server) {
+                    Ok(_) => {
+                        self.room_name.clear();
+                        self.wait_response = true;
+                    }
+                    Err(error) => self.current_message_status = Some(format!("Failed to create room: {}", error)),
+                }
+            }
+        }
+
+        if self.current_message_status.is_some() {
+            ui.label(self.current_message_status.as_ref().unwrap());
+        }
+
+        ui.separator();
+        if ui.button("Back").clicked() {
+            self.current_message_status = None;
+            self.current_menu = Menu::CommunicationServer;
+        }
+    }
+
+    fn room(&mut self, ui: &mut Ui, room: RoomName) {
+        ui.heading(format!("Room {}", room));
+        ui.separator();
+
+        while self.wait_response {
+            if self.client.response_received {
+                self.current_message_status = None;
+                self.wait_response = false;
+            }
+            if let Some(error) = &self.client.external_error {
+                self.current_message_status = Some(format!("Failed to send message: {}", error));
+                self.wait_response = false;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        match self.client.room_inbox.get(&(self.current_server, room.clone())) {
+            Some(messages) if !messages.is_empty() => {
+                for (sender, message) in messages {
+                    if *sender == self.client.id {
+                        ui.label(format!("You:\n{}", message));
+                    } else {
+                        ui.label(format!("Client {}:\n{}", sender, message));
+                    }
+                }
+            }
+            _ => {
+                ui.label("No messages yet.");
+            }
+        }
+        ui.separator();
+
+        let message = self.current_message.as_mut().unwrap();
+
+        ui.horizontal(|ui| {
+            ui.label("Your message: ");
+            ui.text_edit_singleline(message);
+        });
+
+        if self.current_message_status.is_some() {
+            ui.label(self.current_message_status.as_ref().unwrap());
+        }
+
+        if ui.button("Send").clicked() {
+            if message.trim().is_empty() {
+                self.current_message_status = Some("Message cannot be empty.".to_string());
+            } else {
+                match self.client.send_message_to_room(room.clone(), message.trim().to_string(), self.current_server) {
+                    Ok(_) => {
+                        message.clear();
+                        self.wait_response = true;
+                    }
+                    Err(error) => self.current_message_status = Some(format!("Failed to send message: {}", error)),
+                };
+            }
+        }
+
+        if ui.button("Leave").clicked() {
+            match self.client.request_to_leave_room(room, self.current_server) {
+                Ok(_) => {
+                    self.wait_response = true;
+                    self.current_menu = Menu::Rooms;
+                }
+                Err(error) => self.current_message_status = Some(format!("Failed to leave room: {}", error)),
+            }
+        }
+
+        ui.separator();
+        if ui.button("Back").clicked() {
+            self.current_message = Some(String::new());
+            self.current_message_status = None;
+            self.current_menu = Menu::Rooms;
+        }
+    }
+
+    fn ticks(status: MessageStatus) -> &'static str {
+        match status {
+            MessageStatus::Sent => "✔",
+            MessageStatus::Queued => "✔ (offline)",
+            MessageStatus::Delivered => "✔✔",
+            MessageStatus::Read => "✔✔ read",
+        }
+    }
+
     fn ask_type(&mut self, ui: &mut Ui) {
         ui.heading("Request server type");
         ui.separator();
@@ -352,6 +553,41 @@ impl <'a> ChatGUI<'a> {
         }
     }
 
+    fn unregister_client(&mut self, ui: &mut Ui) {
+        ui.heading("Request to unregister");
+        ui.separator();
+
+        ui.label("Requesting to unregister...");
+        match self.client.request_to_unregister(self.current_server) {
+            Ok(_) => self.wait_response = true,
+            Err(error) => {
+                self.current_message_status = Some(format!("Failed to unregister: {}", error));
+            }
+        }
+
+        while self.wait_response {
+            if self.client.response_received {
+                self.current_message_status = Some("You have unregistered successfully!".to_string());
+                self.wait_response = false;
+            }
+            if let Some(error) = &self.client.external_error {
+                self.current_message_status = Some(format!("Failed to unregister: {}", error));
+                self.wait_response = false;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        if self.current_message_status.is_some() {
+            ui.label(self.current_message_status.as_ref().unwrap());
+        }
+
+        ui.separator();
+        if ui.button("Back").clicked() {
+            self.current_message_status = None;
+            self.current_menu = Menu::ChooseServer;
+        }
+    }
+
     fn ask_clients_list(&mut self, ui: &mut Ui) {
         ui.heading("Request list of clients");
         ui.separator();
@@ -1,11 +1,7 @@
-use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    fmt::Debug,
-};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
 
 use crossbeam_channel::{select_biased, Receiver, Sender};
 use log::{info, debug, warn, error};
-use serde::Serialize;
 
 use wg_2024::{
     network::{NodeId, SourceRoutingHeader},
@@ -13,8 +9,11 @@ use wg_2024::{
 };
 
 use crate::{
-    general_use::{ClientCommand, ClientEvent, Message, Query, Response, ServerType},
-    clients::Client
+    general_use::{ClientCommand, ClientEvent, HistoryEntry, Message, MessageId, MessageStatus, Query, Response, RoomName, ServerCapabilities, ServerType, PROTOCOL_VERSION},
+    clients::Client,
+    fragmentation::Reassembler,
+    encoding::Encoding,
+    id_allocator::IdAllocator,
 };
 use super::{MessageFragments, ChatGUI};
 
@@ -32,23 +31,34 @@ pub struct ChatClientDanylo {
 
     // Servers and clients
     pub servers: HashMap<NodeId, ServerType>,                   // IDs and types of the available servers
+    pub capabilities: HashMap<NodeId, ServerCapabilities>,      // What the servers told us they support
     pub is_registered: HashMap<NodeId, bool>,                   // Registration status on servers
     pub clients: HashMap<NodeId, Vec<NodeId>>,                  // Available clients on different servers
 
     // Used IDs
     pub session_ids: Vec<u64>,                                  // Used session IDs
     pub flood_ids: Vec<u64>,                                    // Used flood IDs
+    pub ids: IdAllocator,                                       // Generator of new session and flood IDs
 
     // Network
     pub topology: HashMap<NodeId, HashSet<NodeId>>,             // Nodes and their neighbours
     pub routes: HashMap<NodeId, Vec<NodeId>>,                   // Routes to the servers
 
     // Message queues
+    pub encoding: Encoding,                                     // Encoding of the messages sent to the servers
     pub messages_to_send: HashMap<u64, MessageFragments>,       // Queue of messages to be sent for different sessions
-    pub fragments_to_reassemble: HashMap<u64, Vec<Fragment>>,   // Queue of fragments to be reassembled for different sessions
+    pub fragments_to_reassemble: HashMap<u64, Reassembler>,     // Fragments to be reassembled for different sessions
 
     // Inbox
-    pub inbox: Vec<(NodeId, Message)>,                          // Messages with their senders
+    pub inbox: Vec<(NodeId, MessageId, Message)>,               // Messages with their senders
+    pub unread: HashMap<MessageId, NodeId>,                     // Messages of the inbox not read yet, with the server they came from
+    pub sent_messages: Vec<(NodeId, MessageId, Message, MessageStatus)>, // Messages sent to other clients, with their status
+    pub history_cursors: HashMap<(NodeId, NodeId), u64>,        // Next history message to ask, by server and client
+    pub history_pages: HashMap<(NodeId, NodeId), Vec<HistoryEntry>>, // History being received page by page, by server and client
+
+    // Rooms
+    pub rooms: HashMap<NodeId, Vec<RoomName>>,                  // Rooms available on different servers
+    pub room_inbox: HashMap<(NodeId, RoomName), Vec<(NodeId, Message)>>, // Conversations of the joined rooms, by server and room
 
     // For GUI
     pub response_received: bool,                                // Flag to indicate if a response was received for the last request
@@ -72,15 +82,24 @@ impl Client for ChatClientDanylo {
             controller_send,
             controller_recv,
             servers: HashMap::new(),
+            capabilities: HashMap::new(),
             is_registered: HashMap::new(),
             clients: HashMap::new(),
             session_ids: Vec::new(),
             flood_ids: Vec::new(),
+            ids: IdAllocator::new(id),
             topology: HashMap::new(),
             routes: HashMap::new(),
+            encoding: Encoding::default(),
             messages_to_send: HashMap::new(),
             fragments_to_reassemble: HashMap::new(),
             inbox: Vec::new(),
+            unread: HashMap::new(),
+            sent_messages: Vec::new(),
+            history_cursors: HashMap::new(),
+            history_pages: HashMap::new(),
+            rooms: HashMap::new(),
+            room_inbox: HashMap::new(),
             response_received: false,
             external_error: None,
             flood_responses: Vec::new(),
@@ -172,6 +191,16 @@ impl ChatClientDanylo {
                     Err(err) => error!("Failed to request server type: {}", err),
                 };
             }
+            ClientCommand::SendMessageTo(server_id, to, message) => {
+                match self.send_message_to(to, message, server_id) {
+                    Ok(_) => info!("Message to client {} sent successfully", to),
+                    Err(err) => error!("Failed to send message to client {}: {}", to, err),
+                };
+            }
+            ClientCommand::SetEncoding(encoding) => {
+                self.encoding = encoding;
+                info!("Encoding of the next messages set to {:?}", encoding);
+            }
             // -------------- for tests -------------- \\
             _ => {}
         }
@@ -319,21 +348,34 @@ impl ChatClientDanylo {
     }
 
     /// ###### Handles a received message fragment.
-    /// Adds the fragment to the collection for the session and checks if it is the last fragment.
-    /// If it is the last fragment, reassembles the message and processes the server response.
+    /// Adds the fragment to the reassembler of the session, fragments may arrive in any order.
+    /// Once all the fragments are received, reassembles the message and processes the server response.
     fn handle_fragment(&mut self, fragment: Fragment, session_id: u64, server_id: NodeId) {
         debug!("Handling fragment for session {}: {:?}", session_id, fragment);
 
-        // Retrieve or create a vector to store fragments for the session.
-        let fragments = self.fragments_to_reassemble.entry(session_id).or_insert_with(Vec::new);
+        // Retrieve or create the reassembler for the session.
+        let reassembler = match self.fragments_to_reassemble.entry(session_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match Reassembler::new(fragment.total_n_fragments) {
+                Some(reassembler) => entry.insert(reassembler),
+                None => {
+                    warn!("Message of session {} has too many fragments ({})", session_id, fragment.total_n_fragments);
+                    return;
+                }
+            },
+        };
+
+        if !reassembler.insert(&fragment) {
+            warn!("Fragment {} doesn't belong to the message of session {}", fragment.fragment_index, session_id);
+            return;
+        }
 
-        // Add the current fragment to the collection.
-        fragments.push(fragment.clone());
+        // Check if all the fragments of the message are received.
+        if let Some(data) = reassembler.take_if_complete() {
+            self.fragments_to_reassemble.remove(&session_id);
 
-        // Check if the current fragment is the last one in the sequence.
-        if fragment.fragment_index == fragment.total_n_fragments - 1 {
             // Reassemble the fragments into a complete message and process it.
-            let message = self.reassemble(session_id);
+            let message = self.reassemble(session_id, data);
             self.handle_server_response(message, server_id);
         }
     }
@@ -348,16 +390,68 @@ impl ChatClientDanylo {
                 Response::ServerType(server_type) => {
                     self.handle_server_type(server_id, server_type);
                 },
+                Response::Capabilities(capabilities) => {
+                    self.handle_capabilities(server_id, capabilities);
+                },
                 Response::ClientRegistered => {
                     self.handle_client_registered(server_id);
                 }
+                Response::ClientUnregistered => {
+                    self.handle_client_unregistered(server_id);
+                }
                 Response::ListClients(clients) => {
                     self.handle_clients_list(server_id, clients);
                 }
-                Response::MessageFrom(from, message) => {
+                Response::MessageDelivered(to, message_id) => {
+                    info!("Message to {} delivered", to);
+
+                    self.update_message_status(message_id, MessageStatus::Delivered);
+                    self.response_received = true;
+                }
+                Response::MessageQueued(to, message_id) => {
+                    info!("Client {} is offline, the message will be delivered later", to);
+
+                    self.update_message_status(message_id, MessageStatus::Queued);
+                    self.response_received = true;
+                }
+                Response::MessageRead(by, message_id) => {
+                    info!("Message read by {}", by);
+
+                    self.update_message_status(message_id, MessageStatus::Read);
+                }
+                Response::MessageFrom(from, message_id, message) => {
                     info!("New message from {}: {:?}", from, &message);
 
-                    self.inbox.insert(0, (from, message));
+                    self.inbox.insert(0, (from, message_id, message));
+                    self.unread.insert(message_id, server_id);
+                }
+                Response::History(peer, entries, next) => {
+                    self.handle_history(server_id, peer, entries, next);
+                }
+                Response::ListRooms(rooms) => {
+                    self.handle_rooms_list(server_id, rooms);
+                }
+                Response::RoomJoined(room) => {
+                    info!("Joined room {} on server {}", room, server_id);
+
+                    self.room_inbox.entry((server_id, room)).or_default();
+                    self.response_received = true;
+                }
+                Response::RoomLeft(room) => {
+                    info!("Left room {} on server {}", room, server_id);
+
+                    self.room_inbox.remove(&(server_id, room));
+                    self.response_received = true;
+                }
+                Response::RoomMessageFrom(room, from, message) => {
+                    info!("New message in room {} from {}: {:?}", room, from, &message);
+
+                    self.room_inbox.entry((server_id, room)).or_default().push((from, message));
+
+                    // Our own messages come back as confirmation.
+                    if from == self.id {
+                        self.response_received = true;
+                    }
                 }
                 Response::Err(error) => {
                     self.handle_response_error(server_id, error);
@@ -385,6 +479,22 @@ impl ChatClientDanylo {
         self.response_received = true;
     }
 
+    /// ###### Handles the handshake response.
+    /// Stores the capabilities of the server, so that the queries it doesn't support are not sent,
+    /// then handles its type as for a plain server type response.
+    fn handle_capabilities(&mut self, server_id: NodeId, capabilities: ServerCapabilities) {
+        if capabilities.protocol_version != PROTOCOL_VERSION {
+            warn!(
+                "Server {} speaks protocol version {}, ours is {}",
+                server_id, capabilities.protocol_version, PROTOCOL_VERSION
+            );
+        }
+
+        let server_type = capabilities.server_type;
+        self.capabilities.insert(server_id, capabilities);
+        self.handle_server_type(server_id, server_type);
+    }
+
     /// ###### Handles the client registration response.
     /// Updates the registration status for the specified server and marks the response as received.
     fn handle_client_registered(&mut self, server_id: NodeId) {
@@ -394,6 +504,16 @@ impl ChatClientDanylo {
         self.response_received = true;
     }
 
+    /// ###### Handles the client unregistration response.
+    /// Updates the registration status for the specified server and marks the response as received.
+    fn handle_client_unregistered(&mut self, server_id: NodeId) {
+        info!("Client unregistered successfully.");
+
+        self.is_registered.insert(server_id, false);
+        self.clients.remove(&server_id);
+        self.response_received = true;
+    }
+
     /// ###### Handles the list of clients received from the server.
     /// Updates the list of available clients and marks the response as received.
     fn handle_clients_list(&mut self, server_id: NodeId, clients: Vec<NodeId>) {
@@ -403,6 +523,61 @@ impl ChatClientDanylo {
         self.response_received = true;
     }
 
+    /// ###### Handles a page of the history of a conversation.
+    /// Asks the next page if there is one, otherwise adds the messages we missed to the inbox
+    /// and marks the response as received.
+    fn handle_history(&mut self, server_id: NodeId, peer: NodeId, entries: Vec<HistoryEntry>, next: Option<u64>) {
+        info!("History page with client {} received from server {}", peer, server_id);
+
+        self.history_pages.entry((server_id, peer)).or_default().extend(entries);
+
+        if let Some(since) = next {
+            if let Err(err) = self.create_and_send_message(Query::AskHistory(peer, since), server_id) {
+                error!("Failed to request next history page: {}", err);
+
+                self.history_pages.remove(&(server_id, peer));
+                self.external_error = Some(err);
+            }
+            return;
+        }
+
+        let history = self.history_pages.remove(&(server_id, peer)).unwrap_or_default();
+        if let Some(last) = history.last() {
+            self.history_cursors.insert((server_id, peer), last.seq + 1);
+        }
+
+        // Messages of the peer already in the inbox are not added twice.
+        let missed: Vec<HistoryEntry> = history
+            .into_iter()
+            .filter(|entry| entry.from == peer)
+            .filter(|entry| !self.inbox.iter().any(|(_, message_id, _)| *message_id == entry.id))
+            .collect();
+
+        // The missed messages go on top, the newest first.
+        for entry in missed {
+            self.inbox.insert(0, (peer, entry.id, entry.message));
+            self.unread.insert(entry.id, server_id);
+        }
+        self.response_received = true;
+    }
+
+    /// ###### Updates the status of a sent message.
+    /// A status never goes back, a late delivery confirmation doesn't hide that the message was read.
+    fn update_message_status(&mut self, message_id: MessageId, status: MessageStatus) {
+        if let Some((_, _, _, current)) = self.sent_messages.iter_mut().find(|(_, id, _, _)| *id == message_id) {
+            *current = (*current).max(status);
+        }
+    }
+
+    /// ###### Handles the list of rooms received from the server.
+    /// Updates the list of available rooms and marks the response as received.
+    fn handle_rooms_list(&mut self, server_id: NodeId, rooms: Vec<RoomName>) {
+        info!("List of rooms received successfully.");
+
+        self.rooms.insert(server_id, rooms);
+        self.response_received = true;
+    }
+
     /// ###### Handles the response error.
     /// Logs the error and takes appropriate action based on the error type.
     fn handle_response_error(&mut self, server_id: NodeId, error: String) {
@@ -553,8 +728,8 @@ impl ChatClientDanylo {
         self.routes.clear();
         self.topology.clear();
 
-        // Generate a new flood ID, incrementing the last one or starting at 1 if none exists.
-        let flood_id = self.flood_ids.last().map_or(1, |last| last + 1);
+        // Generate a new flood ID, unique among all the nodes.
+        let flood_id = self.ids.next_flood_id();
         self.flood_ids.push(flood_id);
 
         // Create a new flood request initialized with the generated flood ID, the current node's ID, and its type.
@@ -564,8 +739,8 @@ impl ChatClientDanylo {
             NodeType::Client,
         );
 
-        // Generate a new session ID, incrementing the last one or starting at 1 if none exists.
-        let session_id = self.session_ids.last().map_or(1, |last| last + 1);
+        // Generate a new session ID, unique among all the nodes.
+        let session_id = self.ids.next_session_id();
         self.session_ids.push(session_id);
 
         // Create a new packet with the flood request and session ID.
@@ -629,6 +804,24 @@ impl ChatClientDanylo {
         }
     }
 
+    /// ###### Requests to unregister the client from a specified server.
+    /// Sends an unregistration query to the server and waits for a response.
+    pub fn request_to_unregister(&mut self, server_id: NodeId) -> Result<(), String> {
+        info!("Requesting to unregister from server {}", server_id);
+
+        let result = self.create_and_send_message(Query::UnregisterClient(self.id), server_id);
+
+        match result {
+            Ok(_) => {
+                Ok(())
+            }
+            Err(err) => {
+                error!("Failed to unregister client: {}", err);
+                Err(err)
+            },
+        }
+    }
+
     /// ###### Requests the list of clients from a specified server.
     /// Sends a query to the server and waits for a response.
     pub fn request_clients_list(&mut self, server_id: NodeId) -> Result<(), String> {
@@ -652,11 +845,13 @@ impl ChatClientDanylo {
     pub fn send_message_to(&mut self, to: NodeId, message: Message, server_id: NodeId) -> Result<(), String> {
         info!("Sending message to client {} via server {}", to, server_id);
 
-        let result = self.create_and_send_message(Query::SendMessageTo(to, message), server_id);
+        let message_id = self.ids.next_message_id();
+        let result = self.create_and_send_message(Query::SendMessageTo(to, message_id, message.clone()), server_id);
 
         match result {
             Ok(_) => {
                 info!("Message sent successfully.");
+                self.sent_messages.push((to, message_id, message, MessageStatus::Sent));
                 Ok(())
             }
             Err(err) => {
@@ -666,10 +861,141 @@ impl ChatClientDanylo {
         }
     }
 
+    /// ###### Sends the read receipts of the unread messages of the inbox.
+    /// The messages are marked as read even if a receipt can't be sent, the sender just won't know.
+    pub fn mark_inbox_as_read(&mut self) {
+        let unread: Vec<(MessageId, NodeId)> = self.unread.drain().collect();
+
+        for (message_id, server_id) in unread {
+            let Some(&(from, _, _)) = self.inbox.iter().find(|(_, id, _)| *id == message_id) else {
+                continue;
+            };
+
+            if let Err(err) = self.create_and_send_message(Query::MarkAsRead(from, message_id), server_id) {
+                error!("Failed to send read receipt to client {}: {}", from, err);
+            }
+        }
+    }
+
+    /// ###### Requests the history of the conversation with a client from a specified server.
+    /// Only the messages after the last history received are asked, the pages are asked one after the other.
+    pub fn request_history(&mut self, peer: NodeId, server_id: NodeId) -> Result<(), String> {
+        info!("Requesting history with client {} from server {}", peer, server_id);
+
+        let since = self.history_cursors.get(&(server_id, peer)).copied().unwrap_or(0);
+        let result = self.create_and_send_message(Query::AskHistory(peer, since), server_id);
+
+        match result {
+            Ok(_) => {
+                Ok(())
+            }
+            Err(err) => {
+                error!("Failed to request history: {}", err);
+                Err(err)
+            },
+        }
+    }
+
+    /// ###### Requests the list of rooms from a specified server.
+    pub fn request_rooms_list(&mut self, server_id: NodeId) -> Result<(), String> {
+        info!("Requesting rooms list from server {}", server_id);
+
+        let result = self.create_and_send_message(Query::AskListRooms, server_id);
+
+        match result {
+            Ok(_) => {
+                Ok(())
+            }
+            Err(err) => {
+                error!("Failed to request rooms list: {}", err);
+                Err(err)
+            },
+        }
+    }
+
+    /// ###### Requests to create a room on a specified server, the client joins it.
+    pub fn request_to_create_room(&mut self, room: RoomName, server_id: NodeId) -> Result<(), String> {
+        info!("Requesting to create room {} on server {}", room, server_id);
+
+        let result = self.create_and_send_message(Query::CreateRoom(room), server_id);
+
+        match result {
+            Ok(_) => {
+                Ok(())
+            }
+            Err(err) => {
+                error!("Failed to create room: {}", err);
+                Err(err)
+            },
+        }
+    }
+
+    /// ###### Requests to join a room on a specified server.
+    pub fn request_to_join_room(&mut self, room: RoomName, server_id: NodeId) -> Result<(), String> {
+        info!("Requesting to join room {} on server {}", room, server_id);
+
+        let result = self.create_and_send_message(Query::JoinRoom(room), server_id);
+
+        match result {
+            Ok(_) => {
+                Ok(())
+            }
+            Err(err) => {
+                error!("Failed to join room: {}", err);
+                Err(err)
+            },
+        }
+    }
+
+    /// ###### Requests to leave a room on a specified server.
+    pub fn request_to_leave_room(&mut self, room: RoomName, server_id: NodeId) -> Result<(), String> {
+        info!("Requesting to leave room {} on server {}", room, server_id);
+
+        let result = self.create_and_send_message(Query::LeaveRoom(room), server_id);
+
+        match result {
+            Ok(_) => {
+                Ok(())
+            }
+            Err(err) => {
+                error!("Failed to leave room: {}", err);
+                Err(err)
+            },
+        }
+    }
+
+    /// ###### Sends a message to all the members of a room.
+    pub fn send_message_to_room(&mut self, room: RoomName, message: Message, server_id: NodeId) -> Result<(), String> {
+        info!("Sending message to room {} via server {}", room, server_id);
+
+        let result = self.create_and_send_message(Query::SendMessageToRoom(room, message), server_id);
+
+        match result {
+            Ok(_) => {
+                Ok(())
+            }
+            Err(err) => {
+                error!("Failed to send message to room: {}", err);
+                Err(err)
+            },
+        }
+    }
+
     /// ###### Creates and sends a message to a specified server.
-    /// Serializes the data, splits it into fragments, and sends the first fragment.
-    fn create_and_send_message<T: Serialize + Debug>(&mut self, data: T, server_id: NodeId) -> Result<(), String> {
-        debug!("Creating and sending message to server {}: {:?}", server_id, data);
+    /// Serializes the query, splits it into fragments, and sends the first fragment.
+    /// Queries that the server said it doesn't support are not sent,
+    /// and the encoding falls back to JSON if the server doesn't read ours.
+    fn create_and_send_message(&mut self, query: Query, server_id: NodeId) -> Result<(), String> {
+        debug!("Creating and sending message to server {}: {:?}", server_id, query);
+
+        // Check what the server supports, servers that didn't send their capabilities get everything.
+        let encoding = match self.capabilities.get(&server_id) {
+            Some(capabilities) if !capabilities.supports(&query) => {
+                return Err(format!("Server {} doesn't support {}", server_id, query.name()));
+            }
+            Some(capabilities) => capabilities.encoding_for(self.encoding),
+            None => self.encoding,
+        };
 
         // Find or create a route.
         let hops = if let Some(route) = self.routes.get(&server_id) {
@@ -679,12 +1005,12 @@ impl ChatClientDanylo {
         };
 
         // Generate a new session ID.
-        let session_id = self.session_ids.last().map_or(1, |last| last + 1);
+        let session_id = self.ids.next_session_id();
         self.session_ids.push(session_id);
 
         // Create message (split the message into fragments) and send first fragment.
         let mut message = MessageFragments::new(session_id, hops);
-        if message.create_message_of(data) {
+        if message.create_message_of(query, encoding) {
             self.messages_to_send.insert(session_id, message.clone());
             self.send_to_next_hop(message.get_fragment_packet(0).unwrap())
         } else {
@@ -731,64 +1057,17 @@ impl ChatClientDanylo {
     }
 
 
-    /// ###### Reassembles the fragments for a given session into a complete message.
-    /// Returns the reassembled message or an error if reassembly fails.
-    fn reassemble(&mut self, session_id: u64) -> Option<Response> {
+    /// ###### Deserializes the reassembled data of a given session into a complete message.
+    /// Returns the reassembled message or an error if deserialization fails.
+    fn reassemble(&mut self, session_id: u64, data: Vec<u8>) -> Option<Response> {
         debug!("Reassembling message for session {}", session_id);
 
-        // Retrieve the fragments for the given session.
-        let fragments = match self.fragments_to_reassemble.get_mut(&session_id) {
-            Some(fragments) => fragments,
-            None => {
-                error!("No fragments found for session {}", session_id);
-                return None;
-            },
-        };
-
-        // Ensure all fragments belong to the same message by checking the total number of fragments.
-        let total_n_fragments = match fragments.first() {
-            Some(first) => first.total_n_fragments,
-            None => {
-                error!("Fragment list is empty for session {}", session_id);
-                return None;
-            },
-        };
-
-        // Check if the number of fragments matches the expected total.
-        if fragments.len() as u64 != total_n_fragments {
-            error!(
-                "Incorrect number of fragments for session {}: expected {}, got {}",
-                session_id,
-                total_n_fragments,
-                fragments.len()
-            );
-            return None;
-        }
-
-        // Collect data from all fragments.
-        let mut result = Vec::new();
-        for fragment in fragments {
-            result.extend_from_slice(&fragment.data[..fragment.length as usize]);
-        }
-
-        // Convert the collected data into a string.
-        let reassembled_string = match String::from_utf8(result) {
-            Ok(string) => string,
-            Err(err) => {
-                error!(
-                    "Failed to convert data to string for session {}: {}",
-                    session_id, err
-                );
-                return None;
-            },
-        };
-
-        // Attempt to deserialize the string into an object.
-        match serde_json::from_str(&reassembled_string) {
-            Ok(deserialized) => Some(deserialized),
+        // Attempt to deserialize the data into an object, MessagePack if it is marked as such, JSON otherwise.
+        match Encoding::decode(&data) {
+            Ok((_, deserialized)) => Some(deserialized),
             Err(err) => {
                 error!(
-                    "Failed to deserialize JSON for session {}: {}",
+                    "Failed to deserialize message for session {}: {}",
                     session_id, err
                 );
                 None
@@ -1,4 +1,4 @@
-use crate::general_use::{ClientId, FloodId, Message, ServerId, ServerType, SessionId};
+use crate::general_use::{ClientId, FloodId, Message, MessageId, MessageStatus, ServerId, ServerType, SessionId};
 use crate::ui_traits::{crossbeam_to_tokio_bridge, Monitoring};
 use serde::Serialize;
 use std::collections::{HashMap, HashSet};
@@ -26,7 +26,9 @@ pub struct ChatClientDisplayData {
     available_clients: HashMap<ServerId, Vec<ClientId>>,
 
     // Inbox
-    received_messages: Vec<(ClientId, Message)>,
+    received_messages: Vec<(ClientId, MessageId, Message)>,
+    unread_messages: Vec<MessageId>,
+    sent_messages: Vec<(ClientId, MessageId, Message, MessageStatus)>,
 }
 
 
@@ -71,6 +73,8 @@ impl Monitoring for ChatClientDanylo {
                             registered_communication_servers: self.is_registered.clone(),
                             available_clients: self.clients.clone(),
                             received_messages: self.inbox.clone(),
+                            unread_messages: self.unread.keys().cloned().collect(),
+                            sent_messages: self.sent_messages.clone(),
                         };
 
                         // Serialize the DisplayData to MessagePack binary
@@ -1,5 +1,10 @@
 use serde::Serialize;
 
+use crate::{
+    encoding::Encoding,
+    fragmentation::Fragmenter,
+};
+
 use wg_2024::{
     network::{NodeId, SourceRoutingHeader},
     packet::{Fragment, Packet, PacketType},
@@ -25,33 +30,17 @@ impl MessageFragments {
         }
     }
 
-    /// ###### Serializes the provided data and splits it into smaller fragments for sending.
-    pub fn create_message_of<T: Serialize>(&mut self, data: T) -> bool {
-        let serialized_message = match serde_json::to_string(&data) {
-            Ok(string) => string,
+    /// ###### Serializes the provided data with the given encoding and splits it into smaller fragments for sending.
+    pub fn create_message_of<T: Serialize>(&mut self, data: T, encoding: Encoding) -> bool {
+        let serialized_message = match encoding.encode(&data) {
+            Ok(bytes) => bytes,
             Err(_) => return false,
         };
 
-        self.fragments = self.fragment(&serialized_message);
+        self.fragments = Fragmenter::fragment(&serialized_message);
         true
     }
 
-    /// ###### Splits a serialized message into fragments of a fixed size.
-    pub fn fragment(&mut self, serialized_msg: &str) -> Vec<Fragment> {
-        let serialized_msg_in_bytes = serialized_msg.as_bytes();
-        let length_response = serialized_msg_in_bytes.len();
-
-        let n_fragments = (length_response + 127) / 128;
-        (0..n_fragments)
-            .map(|i| {
-                let start = i * 128;
-                let end = ((i + 1) * 128).min(length_response);
-                let fragment_data = &serialized_msg[start..end];
-                Fragment::from_string(i as u64, n_fragments as u64, fragment_data.to_string())
-            })
-            .collect()
-    }
-
     /// ###### Retrieves the packet for the specified fragment index.
     pub fn get_fragment_packet(&self, fragment_index: usize) -> Option<Packet> {
         if let Some(fragment) = self.fragments.get(fragment_index).cloned() {
@@ -0,0 +1,72 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+///First byte of MessagePack payloads. JSON payloads have no header, so nodes that only read plain JSON
+///still understand them, and valid JSON never starts with this byte.
+const MESSAGE_PACK_MARKER: u8 = 1;
+
+///Format of the payload of a session, MessagePack payloads are marked by their first byte
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl Encoding {
+    ///Serializes the value, MessagePack is preceded by its marker byte and JSON is left as it is
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Encoding::MessagePack => {
+                let mut data = vec![MESSAGE_PACK_MARKER];
+                rmp_serde::encode::write_named(&mut data, value).map_err(|e| e.to_string())?;
+                Ok(data)
+            }
+        }
+    }
+
+    ///Deserializes the payload as MessagePack if it starts with the marker, as JSON otherwise
+    pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<(Self, T), String> {
+        match data.split_first() {
+            None => Err("Empty payload".to_string()),
+            Some((&MESSAGE_PACK_MARKER, payload)) => rmp_serde::from_slice(payload)
+                .map(|value| (Encoding::MessagePack, value))
+                .map_err(|e| e.to_string()),
+            Some(_) => serde_json::from_slice(data)
+                .map(|value| (Encoding::Json, value))
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::general_use::{Query, Response};
+
+    #[test]
+    fn json_is_sent_without_header() {
+        let query = Query::AskListClients;
+        assert_eq!(Encoding::Json.encode(&query).unwrap(), serde_json::to_vec(&query).unwrap());
+    }
+
+    #[test]
+    fn both_encodings_round_trip() {
+        let response = Response::MessageFrom(4, 7, "héllo".to_string());
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let data = encoding.encode(&response).unwrap();
+            let (decoded_encoding, decoded): (Encoding, Response) = Encoding::decode(&data).unwrap();
+            assert_eq!(decoded_encoding, encoding);
+            assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&response).unwrap());
+        }
+    }
+
+    #[test]
+    fn empty_and_garbage_payloads_are_errors() {
+        assert!(Encoding::decode::<Query>(&[]).is_err());
+        assert!(Encoding::decode::<Query>(b"not json").is_err());
+        assert!(Encoding::decode::<Query>(&[MESSAGE_PACK_MARKER, 0xc1]).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use std::time::Instant;
+use wg_2024::packet::{Fragment, FRAGMENT_DSIZE};
+
+///Most fragments a message can have, bigger messages are refused before allocating anything
+pub const MAX_FRAGMENTS: u64 = 1 << 16;
+
+///Splits messages in fragments, the same way for every node
+pub struct Fragmenter;
+
+impl Fragmenter {
+    ///Splits the bytes in fragments of `FRAGMENT_DSIZE` bytes, the last one may be shorter.
+    ///An empty message still gives one (empty) fragment, so it can be sent.
+    pub fn fragment(data: &[u8]) -> Vec<Fragment> {
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(FRAGMENT_DSIZE).collect()
+        };
+
+        let total_n_fragments = chunks.len() as u64;
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut fragment_data = [0u8; FRAGMENT_DSIZE];
+                fragment_data[..chunk.len()].copy_from_slice(chunk);
+                Fragment {
+                    fragment_index: i as u64,
+                    total_n_fragments,
+                    length: chunk.len() as u8,
+                    data: fragment_data,
+                }
+            })
+            .collect()
+    }
+}
+
+///Rebuilds a message from its fragments, received in any order and possibly more than once
+#[derive(Debug, Clone)]
+pub struct Reassembler {
+    fragments: Vec<Option<Vec<u8>>>,                    // Data of each fragment, by index
+    n_received: usize,
+    last_update: Instant,
+}
+
+impl Reassembler {
+    ///Returns None if the message claims more than MAX_FRAGMENTS fragments
+    pub fn new(total_n_fragments: u64) -> Option<Self> {
+        if total_n_fragments > MAX_FRAGMENTS {
+            return None;
+        }
+        Some(Reassembler {
+            fragments: vec![None; total_n_fragments as usize],
+            n_received: 0,
+            last_update: Instant::now(),
+        })
+    }
+
+    ///Stores the fragment, returns false if it doesn't belong to this message.
+    ///Duplicates are accepted and ignored.
+    pub fn insert(&mut self, fragment: &Fragment) -> bool {
+        if fragment.total_n_fragments as usize != self.fragments.len() {
+            return false;
+        }
+        let Some(slot) = self.fragments.get_mut(fragment.fragment_index as usize) else {
+            return false;
+        };
+
+        if slot.is_none() {
+            let length = (fragment.length as usize).min(FRAGMENT_DSIZE);
+            *slot = Some(fragment.data[..length].to_vec());
+            self.n_received += 1;
+        }
+        self.last_update = Instant::now();
+        true
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.n_received == self.fragments.len()
+    }
+
+    ///Returns the whole message once all the fragments arrived
+    pub fn take_if_complete(&mut self) -> Option<Vec<u8>> {
+        if !self.is_complete() {
+            return None;
+        }
+        Some(self.fragments.iter_mut().filter_map(Option::take).flatten().collect())
+    }
+
+    ///Time of the last fragment received
+    pub fn last_update(&self) -> Instant {
+        self.last_update
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    ///Fragments the data, shuffles and duplicates the fragments, and reassembles them
+    fn round_trip(data: &[u8], rng: &mut StdRng) -> Vec<u8> {
+        let mut fragments = Fragmenter::fragment(data);
+        let n_duplicates = rng.gen_range(0..=fragments.len());
+        let duplicates: Vec<Fragment> = fragments.choose_multiple(rng, n_duplicates).cloned().collect();
+        fragments.extend(duplicates);
+        fragments.shuffle(rng);
+
+        let mut reassembler = Reassembler::new(fragments[0].total_n_fragments).unwrap();
+        for fragment in &fragments {
+            assert!(reassembler.insert(fragment));
+        }
+        reassembler.take_if_complete().unwrap()
+    }
+
+    #[test]
+    fn random_payloads_survive_shuffling_and_duplicates() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..200 {
+            let length = rng.gen_range(0..10 * FRAGMENT_DSIZE);
+            let data: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+            assert_eq!(round_trip(&data, &mut rng), data);
+        }
+    }
+
+    #[test]
+    fn multi_byte_utf8_is_rebuilt_across_fragment_boundaries() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for offset in 0..4 {
+            let text = format!("{}{}", "x".repeat(offset), "héllo wörld ✓ 🚀 ".repeat(40));
+            let rebuilt = round_trip(text.as_bytes(), &mut rng);
+            assert_eq!(String::from_utf8(rebuilt).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn empty_payload_is_one_empty_fragment() {
+        let fragments = Fragmenter::fragment(&[]);
+        assert_eq!(fragments.len(), 1);
+        assert_eq!(fragments[0].total_n_fragments, 1);
+        assert_eq!(fragments[0].length, 0);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        assert!(round_trip(&[], &mut rng).is_empty());
+    }
+
+    #[test]
+    fn exact_multiples_of_the_fragment_size_have_no_extra_fragment() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for n in 1..=4 {
+            let data: Vec<u8> = (0..n * FRAGMENT_DSIZE).map(|i| i as u8).collect();
+            let fragments = Fragmenter::fragment(&data);
+            assert_eq!(fragments.len(), n);
+            assert!(fragments.iter().all(|fragment| fragment.length as usize == FRAGMENT_DSIZE));
+            assert_eq!(round_trip(&data, &mut rng), data);
+        }
+    }
+
+    #[test]
+    fn message_is_incomplete_until_every_fragment_arrives() {
+        let data = vec![1u8; 3 * FRAGMENT_DSIZE + 1];
+        let fragments = Fragmenter::fragment(&data);
+        let mut reassembler = Reassembler::new(fragments.len() as u64).unwrap();
+        for fragment in &fragments[1..] {
+            assert!(reassembler.insert(fragment));
+            assert!(reassembler.take_if_complete().is_none());
+        }
+        assert!(reassembler.insert(&fragments[0]));
+        assert_eq!(reassembler.take_if_complete().unwrap(), data);
+    }
+
+    #[test]
+    fn fragments_of_other_messages_are_rejected() {
+        let fragments = Fragmenter::fragment(&[5u8; 2 * FRAGMENT_DSIZE]);
+        let mut reassembler = Reassembler::new(3).unwrap();
+        assert!(!reassembler.insert(&fragments[0]));
+
+        let mut out_of_range = fragments[1].clone();
+        out_of_range.fragment_index = 2;
+        let mut reassembler = Reassembler::new(2).unwrap();
+        assert!(!reassembler.insert(&out_of_range));
+    }
+
+    #[test]
+    fn too_many_fragments_are_refused() {
+        assert!(Reassembler::new(MAX_FRAGMENTS).is_some());
+        assert!(Reassembler::new(MAX_FRAGMENTS + 1).is_none());
+        assert!(Reassembler::new(u64::MAX).is_none());
+    }
+}
@@ -6,9 +6,12 @@ use wg_2024::{
     network::NodeId,
     packet::Packet,
 };
+use crate::encoding::Encoding;
 
 pub type Message = String;
+pub type MessageId = u64;   //given by the sender, unique in the whole simulation
 pub type File = String;
+pub type RoomName = String;
 pub type ServerId = NodeId;
 pub type ClientId = NodeId;
 pub type DroneId = NodeId;
@@ -17,6 +20,9 @@ pub type FloodId = u64;
 pub type FragmentIndex = u64;
 pub type UsingTimes = u64;  //to measure traffic of fragments in a path.
 
+///Version of our Query/Response protocol, increased at every incompatible change
+pub const PROTOCOL_VERSION: u16 = 1;
+
 ///packet sending status
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NotSentType{
@@ -33,6 +39,15 @@ pub enum Speaker{
     HimOrHer,
 }
 
+///chat message status, seen by its sender, each one comes after the previous ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MessageStatus{
+    Sent,                   //Sent to the server, no answer yet
+    Queued,                 //The recipient is offline, the server keeps it
+    Delivered,              //The recipient received it
+    Read,                   //The recipient read it
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PacketStatus{
     Sent,                   //Successfully sent packet, that is with ack received
@@ -49,7 +64,14 @@ pub enum ServerCommand {
 }
 
 ///Server-Controller
+#[derive(Debug, Clone)]
 pub enum ServerEvent {
+    PacketSent(Packet),
+    ClientRegistered(ClientId),
+    ClientUnregistered(ClientId),
+    MessageForwarded { from: ClientId, to: ClientId },
+    FileServed { client: ClientId, file: String },
+    RouteUnavailable(NodeId),
 }
 
 #[derive(Debug)]
@@ -65,6 +87,8 @@ pub enum ClientCommand {
     RequestMedia(NodeId),
     ShortcutPacket(Packet),
     GetKnownServers,
+    SendMessageTo(ServerId, ClientId, Message),
+    SetEncoding(Encoding),
 }
 
 
@@ -84,7 +108,20 @@ pub enum Query {
     RegisterClient(NodeId),
     UnregisterClient(NodeId),
     AskListClients,
-    SendMessageTo(NodeId, Message),
+    SendMessageTo(NodeId, MessageId, Message),
+    MarkAsRead(NodeId, MessageId),              // The message of this client was read
+    AskHistory(NodeId, u64),                    // Messages with this client, from this sequence number
+    //(Rooms)
+    AskListRooms,
+    CreateRoom(RoomName),
+    JoinRoom(RoomName),
+    LeaveRoom(RoomName),
+    SendMessageToRoom(RoomName, Message),
+
+    //Between Communication Servers
+    FederationHello(Vec<ClientId>),             // A communication server with its clients, asking ours
+    FederationClients(Vec<ClientId>),           // The clients of a communication server
+    RelayMessage(ClientId, ClientId, MessageId, Message), // From a client of the sender to one of ours
 
     //To Content Server
     //(Text)
@@ -94,20 +131,57 @@ pub enum Query {
     AskMedia(String), // String is the reference found in the files
 }
 
+impl Query {
+    ///Name of the variant, used to advertise and check the supported queries
+    pub fn name(&self) -> &'static str {
+        match self {
+            Query::AskType => "AskType",
+            Query::RegisterClient(_) => "RegisterClient",
+            Query::UnregisterClient(_) => "UnregisterClient",
+            Query::AskListClients => "AskListClients",
+            Query::SendMessageTo(_, _, _) => "SendMessageTo",
+            Query::MarkAsRead(_, _) => "MarkAsRead",
+            Query::AskHistory(_, _) => "AskHistory",
+            Query::AskListRooms => "AskListRooms",
+            Query::CreateRoom(_) => "CreateRoom",
+            Query::JoinRoom(_) => "JoinRoom",
+            Query::LeaveRoom(_) => "LeaveRoom",
+            Query::SendMessageToRoom(_, _) => "SendMessageToRoom",
+            Query::FederationHello(_) => "FederationHello",
+            Query::FederationClients(_) => "FederationClients",
+            Query::RelayMessage(_, _, _, _) => "RelayMessage",
+            Query::AskListFiles => "AskListFiles",
+            Query::AskFile(_) => "AskFile",
+            Query::AskMedia(_) => "AskMedia",
+        }
+    }
+}
+
 //Server -> Client
 #[derive(Deserialize, Serialize, Debug)]
 pub enum Response {
     //Common-shared
-    ServerType(ServerType),
+    ServerType(ServerType),                     // Kept to understand servers that don't send the capabilities
+    Capabilities(ServerCapabilities),
 
     //From Communication Server
     ClientRegistered,
-    MessageFrom(NodeId, Message),
+    ClientUnregistered,
+    MessageFrom(NodeId, MessageId, Message),
+    MessageDelivered(ClientId, MessageId),      // The message to this client arrived
+    MessageQueued(ClientId, MessageId),         // This client is offline, it will get the message when back
+    MessageRead(ClientId, MessageId),           // This client read the message
     ListClients(Vec<NodeId>),
+    History(NodeId, Vec<HistoryEntry>, Option<u64>), // Page of the conversation with this client, and where the next page starts
+    //(Rooms)
+    ListRooms(Vec<RoomName>),
+    RoomJoined(RoomName),
+    RoomLeft(RoomName),
+    RoomMessageFrom(RoomName, NodeId, Message), // Also sent back to the sender, as confirmation
 
     //From Content Server
     //(Text)
-    ListFiles(Vec<String>),
+    ListFiles(Vec<FileInfo>),
     File(String),
     //(Media)
     Media(String),
@@ -116,6 +190,45 @@ pub enum Response {
     Err(String)
 }
 
+///Message kept by a communication server, `seq` orders the messages of a conversation
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub seq: u64,
+    pub id: MessageId,
+    pub from: NodeId,
+    pub message: Message,
+}
+
+///File listed by a text server, asked with Query::AskFile(id)
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FileInfo {
+    pub id: u8,
+    pub name: String,
+    pub size: usize,                            // Bytes
+}
+
+///Handshake sent back to Query::AskType.
+///Queries are advertised by name, so a server of another group can list queries we don't know.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ServerCapabilities {
+    pub server_type: ServerType,
+    pub protocol_version: u16,
+    pub supported_queries: Vec<String>,
+    pub max_payload_size: usize,                // Bytes of a reassembled query accepted by the server
+    pub encodings: Vec<Encoding>,
+}
+
+impl ServerCapabilities {
+    pub fn supports(&self, query: &Query) -> bool {
+        self.supported_queries.iter().any(|name| name == query.name())
+    }
+
+    ///The preferred encoding if the server reads it, else plain JSON, which needs no negotiation
+    pub fn encoding_for(&self, preferred: Encoding) -> Encoding {
+        if self.encodings.contains(&preferred) { preferred } else { Encoding::Json }
+    }
+}
+
 ///Material
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, PartialEq, Hash, Eq)]
 pub enum ServerType {
@@ -158,3 +271,49 @@ impl Display for ClientType {
         write!(f, "{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_only_server() -> ServerCapabilities {
+        ServerCapabilities {
+            server_type: ServerType::Communication,
+            protocol_version: PROTOCOL_VERSION,
+            supported_queries: vec!["AskType".to_string(), "AskListClients".to_string()],
+            max_payload_size: 64 * 1024,
+            encodings: vec![Encoding::Json],
+        }
+    }
+
+    #[test]
+    fn unsupported_encoding_falls_back_to_plain_json() {
+        let encoding = json_only_server().encoding_for(Encoding::MessagePack);
+        assert_eq!(encoding, Encoding::Json);
+
+        //What a server of another group, reading plain JSON, receives
+        let query = Query::AskListClients;
+        assert_eq!(encoding.encode(&query).unwrap(), serde_json::to_vec(&query).unwrap());
+    }
+
+    #[test]
+    fn plain_json_of_other_servers_is_decoded() {
+        let data = serde_json::to_vec(&Response::ClientRegistered).unwrap();
+        let (encoding, response): (Encoding, Response) = Encoding::decode(&data).unwrap();
+        assert_eq!(encoding, Encoding::Json);
+        assert!(matches!(response, Response::ClientRegistered));
+
+        let data = serde_json::to_vec(&Query::AskType).unwrap();
+        let (_, query): (Encoding, Query) = Encoding::decode(&data).unwrap();
+        assert!(matches!(query, Query::AskType));
+    }
+
+    #[test]
+    fn supported_encoding_is_kept() {
+        let mut capabilities = json_only_server();
+        capabilities.encodings.push(Encoding::MessagePack);
+        assert_eq!(capabilities.encoding_for(Encoding::MessagePack), Encoding::MessagePack);
+        assert!(capabilities.supports(&Query::AskListClients));
+        assert!(!capabilities.supports(&Query::AskListRooms));
+    }
+}
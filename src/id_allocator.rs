@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use wg_2024::network::NodeId;
+
+use crate::general_use::{FloodId, MessageId, SessionId};
+
+///Bits left to the counter, the node id takes the 8 highest bits
+const COUNTER_BITS: u32 = 56;
+const COUNTER_MASK: u64 = (1 << COUNTER_BITS) - 1;
+
+///Gives session, flood and chat message ids that no other node of the simulation can give.
+///Every id is `node_id << 56 | counter`, so two nodes never share an id,
+///and the same node never gives the same id twice (2^56 ids are more than a simulation uses).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdAllocator {
+    node_id: NodeId,
+    last_session: u64,
+    last_flood: u64,
+    last_message: u64,
+}
+
+impl IdAllocator {
+    pub fn new(node_id: NodeId) -> Self {
+        IdAllocator {
+            node_id,
+            last_session: 0,
+            last_flood: 0,
+            last_message: 0,
+        }
+    }
+
+    pub fn next_session_id(&mut self) -> SessionId {
+        self.last_session = Self::next_counter(self.last_session);
+        self.encode(self.last_session)
+    }
+
+    pub fn next_flood_id(&mut self) -> FloodId {
+        self.last_flood = Self::next_counter(self.last_flood);
+        self.encode(self.last_flood)
+    }
+
+    pub fn next_message_id(&mut self) -> MessageId {
+        self.last_message = Self::next_counter(self.last_message);
+        self.encode(self.last_message)
+    }
+
+    fn next_counter(counter: u64) -> u64 {
+        //Only there to never overflow, a simulation is far from using 2^56 ids
+        if counter >= COUNTER_MASK { 1 } else { counter + 1 }
+    }
+
+    fn encode(&self, counter: u64) -> u64 {
+        ((self.node_id as u64) << COUNTER_BITS) | (counter & COUNTER_MASK)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn ids_of_two_nodes_never_collide() {
+        for (first, second) in [(1, 2), (0, 255), (7, 8)] {
+            let mut first_allocator = IdAllocator::new(first);
+            let mut second_allocator = IdAllocator::new(second);
+
+            let mut ids = HashSet::new();
+            for _ in 0..1000 {
+                assert!(ids.insert(first_allocator.next_session_id()));
+                assert!(ids.insert(second_allocator.next_session_id()));
+            }
+        }
+    }
+
+    #[test]
+    fn node_id_is_in_the_highest_bits() {
+        let mut allocator = IdAllocator::new(42);
+        for id in [allocator.next_session_id(), allocator.next_flood_id(), allocator.next_message_id()] {
+            assert_eq!(id >> COUNTER_BITS, 42);
+            assert_eq!(id & COUNTER_MASK, 1);
+        }
+    }
+
+    #[test]
+    fn counter_wraps_without_touching_the_node_bits() {
+        for node_id in [0, 1, 255] {
+            let mut allocator = IdAllocator::new(node_id);
+            allocator.last_session = COUNTER_MASK - 1;
+
+            let last = allocator.next_session_id();
+            assert_eq!(last >> COUNTER_BITS, node_id as u64);
+            assert_eq!(last & COUNTER_MASK, COUNTER_MASK);
+
+            let wrapped = allocator.next_session_id();
+            assert_eq!(wrapped >> COUNTER_BITS, node_id as u64);
+            assert_eq!(wrapped & COUNTER_MASK, 1);
+        }
+    }
+
+    #[test]
+    fn each_kind_of_id_has_its_own_counter() {
+        let mut allocator = IdAllocator::new(3);
+        allocator.next_session_id();
+        allocator.next_session_id();
+        assert_eq!(allocator.next_flood_id() & COUNTER_MASK, 1);
+        assert_eq!(allocator.next_message_id() & COUNTER_MASK, 1);
+        assert_eq!(allocator.next_session_id() & COUNTER_MASK, 3);
+    }
+}
@@ -1,5 +1,10 @@
 //Mod components
 mod network_initializer;
+mod network_validation;
+mod network_config;
+mod fragmentation;
+mod id_allocator;
+mod encoding;
 mod servers;
 
 mod simulation_controller;
@@ -15,11 +20,21 @@ use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
+use simulation_controller::Scenario;
 
 
 #[tokio::main]  //HERE YOU ARE EXPLICITLY USING MULTITHREADED RUNTIME WITH TOKIO SO SURE THAT YOU ARE
                 //NOT RUNNING EVERYTHING IN ONE THREAD.
 async fn main() {
+    let seed = match seed_from_args() {
+        Ok(seed) => seed,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!("Usage: [--seed <non-negative number>] [--scenario <file>]");
+            return;
+        }
+    };
+
     let url = "ws://localhost:8000";
 
     println!("Connecting to {}", url);
@@ -32,8 +47,23 @@ async fn main() {
     let (tx, mut rx) = mpsc::channel::<String>(1000);
 
     // Network initializer instance
-    let mut my_net = network_initializer::NetworkInitializer::new(tx.clone());
-    my_net.initialize_from_file("input.toml");
+    let mut my_net = network_initializer::NetworkInitializer::new(tx.clone(), seed);
+    if let Some(path) = arg_value("--scenario") {
+        match Scenario::from_file(&path) {
+            Ok(scenario) => my_net.set_scenario(scenario),
+            Err(error) => {
+                eprintln!("{}", error);
+                return;
+            }
+        }
+    }
+    if let Err(errors) = my_net.initialize_from_file("input.toml") {
+        eprintln!("Invalid network configuration:");
+        for error in errors {
+            eprintln!("- {}", error);
+        }
+        return;
+    }
     
     // Spawn a task for writing messages to the WebSocket
     tokio::spawn(async move {
@@ -57,3 +87,23 @@ async fn main() {
     //println!("Client program terminated.");
 }
 
+///Reads the seed given with `--seed <number>`, if any
+fn seed_from_args() -> Result<Option<u64>, String> {
+    match arg_value("--seed") {
+        Some(seed) => seed
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Invalid seed \"{}\": the seed must be a non-negative number", seed)),
+        None => Ok(None),
+    }
+}
+
+///Returns the value following `flag` on the command line, if any
+fn arg_value(flag: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+}
+
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use serde::Deserialize;
+use wg_2024::network::NodeId;
+
+use crate::general_use::{ClientType, ServerType};
+use crate::network_initializer::DroneBrand;
+
+///Optional fields that we accept on top of the WG configuration format
+#[derive(Debug, Default, Deserialize)]
+pub struct ExtendedConfig {
+    pub seed: Option<u64>,
+    #[serde(default)]
+    drone: Vec<DroneEntry>,
+    #[serde(default)]
+    client: Vec<ClientEntry>,
+    #[serde(default)]
+    server: Vec<ServerEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DroneEntry {
+    id: NodeId,
+    brand: Option<DroneBrand>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientEntry {
+    id: NodeId,
+    kind: Option<ClientKind>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServerEntry {
+    id: NodeId,
+    kind: Option<ServerKind>,
+    max_offline_messages: Option<usize>,    // Only for communication servers
+    text_dir: Option<String>,               // Only for text servers, directory of the files to serve
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ClientKind {
+    Chat,
+    Web,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ServerKind {
+    Communication,
+    Text,
+    Media,
+}
+
+impl From<ClientKind> for ClientType {
+    fn from(kind: ClientKind) -> Self {
+        match kind {
+            ClientKind::Chat => ClientType::Chat,
+            ClientKind::Web => ClientType::Web,
+        }
+    }
+}
+
+impl From<ServerKind> for ServerType {
+    fn from(kind: ServerKind) -> Self {
+        match kind {
+            ServerKind::Communication => ServerType::Communication,
+            ServerKind::Text => ServerType::Text,
+            ServerKind::Media => ServerType::Media,
+        }
+    }
+}
+
+impl ExtendedConfig {
+    pub fn drone_brands(&self) -> HashMap<NodeId, DroneBrand> {
+        self.drone.iter()
+            .filter_map(|drone| drone.brand.map(|brand| (drone.id, brand)))
+            .collect()
+    }
+
+    pub fn client_types(&self) -> HashMap<NodeId, ClientType> {
+        self.client.iter()
+            .filter_map(|client| client.kind.map(|kind| (client.id, kind.into())))
+            .collect()
+    }
+
+    pub fn server_types(&self) -> HashMap<NodeId, ServerType> {
+        self.server.iter()
+            .filter_map(|server| server.kind.map(|kind| (server.id, kind.into())))
+            .collect()
+    }
+
+    pub fn max_offline_messages(&self) -> HashMap<NodeId, usize> {
+        self.server.iter()
+            .filter_map(|server| server.max_offline_messages.map(|max| (server.id, max)))
+            .collect()
+    }
+
+    pub fn text_dirs(&self) -> HashMap<NodeId, String> {
+        self.server.iter()
+            .filter_map(|server| server.text_dir.clone().map(|dir| (server.id, dir)))
+            .collect()
+    }
+}
@@ -2,20 +2,21 @@
 use std::{
     collections::HashMap,
     env, fs, thread,
+    path::Path,
 };
 use tokio::sync::Mutex;
 use std::sync::Arc;
 
 use crossbeam_channel::*;
 use rand::prelude::*;
+use serde::Deserialize;
 use tokio::sync::mpsc;
 //Wg libraries
 use wg_2024::{
     config::{Client, Config, Drone, Server},
-    controller::{DroneCommand, DroneEvent},
+    controller::DroneEvent,
     network::NodeId,
     packet::{NodeType, Packet},
-    drone::Drone as TraitDrone,
 };
 
 //Inner libraries
@@ -26,25 +27,14 @@ use crate::{
         client_chen::web_browser_client_traits::WebBrowserClientTrait,
         client_danylo::ChatClientDanylo,
     },
-    general_use::{ClientId, ClientCommand, ClientEvent, ServerEvent, ClientType, ServerType, DroneId, UsingTimes},
-    servers::{content, communication_server::CommunicationServer, text_server::TextServer, media_server::MediaServer, server::Server as ServerTrait},
-    simulation_controller::SimulationController
+    general_use::{ClientId, ClientCommand, ClientEvent, ServerEvent, ClientType, ServerType, UsingTimes},
+    servers::{content, communication_server::{CommunicationServer, DEFAULT_MAX_OFFLINE_MESSAGES}, file_catalogue::FileCatalogue, text_server::TextServer, media_server::MediaServer, server::Server as ServerTrait},
+    simulation_controller::{PacketInfo, Scenario, SimulationController},
+    network_validation::{validate_config, TopologyError},
+    network_config::ExtendedConfig,
 };
 
 
-//Drones
-use rusty_drones::RustyDrone;
-use rolling_drone::RollingDrone;
-use rustable_drone::RustableDrone;
-use rustbusters_drone::RustBustersDrone;
-use rusteze_drone::RustezeDrone;
-use fungi_drone::FungiDrone;
-use bagel_bomber::BagelBomber;
-use skylink::SkyLinkDrone;
-use RF_drone::RustAndFurious;
-//use bobry_w_locie::drone::BoberDrone;
-
-
 //UI
 use crate::ui_traits::Monitoring;
 use crate::ui::start_ui;
@@ -52,7 +42,8 @@ use crate::ui::start_ui;
 
 
 //Drone Enum + iterator over it
-#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum DroneBrand {
     RustyDrone,
     Rustable,
@@ -91,23 +82,46 @@ pub struct NetworkInitializer {
     server_channels: HashMap<NodeId, (Sender<Packet>, ServerType)>,
     drone_brand_usage: HashMap<DroneBrand, UsingTimes>,
     client_type_usage: HashMap<ClientType, UsingTimes>,
+    seed: Option<u64>,
+    rng: StdRng,
+    scenario: Option<Scenario>,
+    packet_subscribers: Vec<Sender<PacketInfo>>,
     sender_to_gui: mpsc::Sender<String>
     //sender_to_gui: mpsc::Sender<Vec<u8>> for message packet
 
 }
 
 impl NetworkInitializer {
-    pub fn new(sender_to_gui: mpsc::Sender<String>) -> Self {
+    ///The seed, if given, takes priority over the one in the config file
+    pub fn new(sender_to_gui: mpsc::Sender<String>, seed: Option<u64>) -> Self {
         Self {
             drone_channels: HashMap::new(),
             client_channels: HashMap::new(),
             server_channels: HashMap::new(),
             drone_brand_usage: DroneBrand::iter().map(|brand| (brand, 0)).collect(),
             client_type_usage: ClientType::iter().map(|client_type| (client_type, 0)).collect(),
+            seed,
+            rng: StdRng::from_entropy(),
+            scenario: None,
+            packet_subscribers: Vec::new(),
             sender_to_gui,
         }
     }
-    pub fn initialize_from_file(&mut self, config_path: &str) {
+
+    ///Scenario executed by the simulation controller once the network is up
+    pub fn set_scenario(&mut self, scenario: Scenario) {
+        self.scenario = Some(scenario);
+    }
+
+    ///Receives every packet recorded by the simulation controller, it has to be asked before the network starts
+    ///since the controller then runs in its own thread
+    pub fn subscribe_to_packets(&mut self) -> Receiver<PacketInfo> {
+        let (sender, receiver) = unbounded();
+        self.packet_subscribers.push(sender);
+        receiver
+    }
+
+    pub fn initialize_from_file(&mut self, config_path: &str) -> Result<(), Vec<TopologyError>> {
         // Log the current directory for debugging purposes
         println!("Current directory: {:?}", env::current_dir().expect("Failed to get current directory"));
 
@@ -120,6 +134,17 @@ impl NetworkInitializer {
         let config_data = fs::read_to_string(config_path).expect("Unable to read config file");
         let config: Config = toml::from_str(&config_data).expect("Failed to parse TOML config");
 
+        // Optional seed, brands and kinds of the nodes, plain WG files just don't have them
+        let extended_config: ExtendedConfig = toml::from_str(&config_data).expect("Failed to parse TOML config");
+
+        // Same seed, same brands, server types and files. Printed so that any run can be replayed
+        let seed = self.seed.or(extended_config.seed).unwrap_or_else(random);
+        println!("Simulation seed: {}", seed);
+        self.rng = StdRng::seed_from_u64(seed);
+
+        // Check the configuration before spawning anything
+        validate_config(&config)?;
+
         // Build the network topology
         let mut topology = HashMap::new();
         for drone in &config.drone {
@@ -148,16 +173,34 @@ impl NetworkInitializer {
         );
 
         // Initialize drones, clients, and servers
-        self.create_drones(config.drone, &mut controller, drone_event_sender, topology.clone());
-        self.create_clients(config.client, &mut controller, client_event_sender, topology.clone());
-        self.create_servers(config.server, &mut controller, server_event_sender, topology.clone());
+        self.create_drones(config.drone, extended_config.drone_brands(), &mut controller, drone_event_sender, topology.clone());
+        self.create_clients(config.client, extended_config.client_types(), &mut controller, client_event_sender, topology.clone());
+        self.create_servers(config.server, extended_config.server_types(), extended_config.max_offline_messages(), extended_config.text_dirs(), &mut controller, server_event_sender, topology.clone());
 
         //Connecting the network
+        controller.state.topology = topology.clone();
         self.connect_nodes(&mut controller, topology);
 
+        // The channels were only needed to connect the nodes. Keeping them would stop a crashed drone
+        // from ever seeing its channel close, the controller keeps its own copies and drops them on crash
+        self.drone_channels.clear();
+        self.client_channels.clear();
+        self.server_channels.clear();
+
+        // Replaying the scenario, if any
+        if let Some(scenario) = self.scenario.take() {
+            controller.load_scenario(scenario);
+        }
+        controller.packet_subscribers.append(&mut self.packet_subscribers);
+
+        // Handling the events of the network
+        thread::spawn(move || controller.run());
+
         /*// Start the user interface
         println!("Starting User Interface");
         start_ui(controller);*/
+
+        Ok(())
     }
 
     ///DRONES GENERATION
@@ -165,10 +208,16 @@ impl NetworkInitializer {
     fn create_drones(
         &mut self,
         drones: Vec<Drone>,
+        drone_brands: HashMap<NodeId, DroneBrand>,
         controller: &mut SimulationController,
         drone_events_sender: Sender<DroneEvent>,
         topology: HashMap<NodeId, Vec<NodeId>>,
     ) {
+        // Brands chosen in the config count as used, so the others are balanced around them
+        for brand in drone_brands.values() {
+            *self.drone_brand_usage.entry(*brand).or_default() += 1;
+        }
+
         for drone in drones {
             // Adding channel to controller
             let (command_sender, command_receiver) = unbounded();
@@ -178,7 +227,8 @@ impl NetworkInitializer {
             let (packet_sender, packet_receiver) = unbounded();
 
             // Storing it for future usages
-            self.drone_channels.insert(drone.id, packet_sender);
+            self.drone_channels.insert(drone.id, packet_sender.clone());
+            controller.packet_senders.insert(drone.id, packet_sender);
 
             // Clone sender for drone events
             let drone_events_sender_clone = drone_events_sender.clone();
@@ -194,52 +244,15 @@ impl NetworkInitializer {
                 drone.pdr,
             );
 
-            // Use helper function or macro (in this case function) to create and spawn drones based on their brand
-            match self.choose_drone_brand_evenly() {
-                DroneBrand::RustyDrone => self.create_and_spawn_drone::<RustyDrone>(controller, drone_params),
-                DroneBrand::RollingDrones => self.create_and_spawn_drone::<RollingDrone>(controller, drone_params),
-                DroneBrand::Rustable => self.create_and_spawn_drone::<RustableDrone>(controller, drone_params),
-                //DroneBrand::RustBusters => self.create_and_spawn_drone::<RustBustersDrone>(controller, drone_params),
-                DroneBrand::RustEze => self.create_and_spawn_drone::<RustezeDrone>(controller, drone_params),
-                DroneBrand::Fungi => self.create_and_spawn_drone::<FungiDrone>(controller, drone_params),
-                DroneBrand::BagelBomber => self.create_and_spawn_drone::<BagelBomber>(controller, drone_params),
-                DroneBrand::SkyLink => self.create_and_spawn_drone::<SkyLinkDrone>(controller, drone_params),
-                DroneBrand::RustAndFurious => self.create_and_spawn_drone::<RustAndFurious>(controller, drone_params),
-                //DroneBrand::BobryWLucie => self.create_and_spawn_drone::<BoberDrone>(controller, drone_params),
-            }
-        }
-    }
-    fn create_and_spawn_drone<T>(
-        &mut self,
-        controller: &mut SimulationController,
-        drone_params: (
-            DroneId,
-            Sender<DroneEvent>,
-            Receiver<DroneCommand>,
-            Receiver<Packet>,
-            HashMap<NodeId, Sender<Packet>>,
-            f32,
-        ),
-    ) where
-        T: TraitDrone + Send + 'static, // Ensure T implements the Drone trait and is Sendable
-    {
-        let (drone_id, event_sender, cmd_receiver, pkt_receiver, pkt_senders, pdr) = drone_params;
-
-        let drone_instance = controller.create_drone::<T>(
-            drone_id,
-            event_sender,
-            cmd_receiver,
-            pkt_receiver,
-            pkt_senders,
-            pdr,
-        );
+            // Brand from the config if given, otherwise the least used one
+            let drone_brand = match drone_brands.get(&drone.id) {
+                Some(&brand) => brand,
+                None => self.choose_drone_brand_evenly(),
+            };
 
-        thread::spawn(move || {
-            match drone_instance {
-                Ok(mut drone) => drone.run(),
-                Err(e) => panic!("Failed to run drone {}: {}", drone_id, e),
-            }
-        });
+            // The controller creates and spawns drones based on their brand, also the ones added later
+            controller.spawn_drone_of_brand(drone_brand, drone_params);
+        }
     }
 
     fn choose_drone_brand_evenly(&mut self) -> DroneBrand {
@@ -253,7 +266,7 @@ impl NetworkInitializer {
                 .cloned()
                 .collect();
             // From those we choose randomly one Brand and we use it
-            if let Some(&chosen_brand) = min_usage_drone_brands.choose(&mut thread_rng()) {
+            if let Some(&chosen_brand) = min_usage_drone_brands.choose(&mut self.rng) {
                 // Update usage count
                 if let Some(usage) = self.drone_brand_usage.get_mut(&chosen_brand) {
                     *usage += 1;
@@ -268,10 +281,16 @@ impl NetworkInitializer {
     fn create_clients(
         &mut self,
         clients: Vec<Client>,
+        client_types: HashMap<NodeId, ClientType>,
         controller: &mut SimulationController,
         client_events_sender: Sender<ClientEvent>,
         topology: HashMap<NodeId, Vec<NodeId>>,
     ) {
+        // Types chosen in the config count as used, so the others are balanced around them
+        for client_type in client_types.values() {
+            *self.client_type_usage.entry(*client_type).or_default() += 1;
+        }
+
         for client in clients {
             // Create command channel between controller and clients
             let (command_sender, command_receiver) = unbounded();
@@ -290,22 +309,26 @@ impl NetworkInitializer {
                 HashMap::new(),
                 );
 
-            let mut client_type;
-            match self.choose_client_type_evenly() {
+            let chosen_type = match client_types.get(&client.id) {
+                Some(&client_type) => client_type,
+                None => self.choose_client_type_evenly(),
+            };
+
+            controller.packet_senders.insert(client.id, packet_sender.clone());
+
+            // Each kind is spawned with the client implementing it, and registered with that same kind
+            match chosen_type {
                 ClientType::Web => {
-                    client_type = ClientType::Chat;
                     self.create_and_spawn_client_with_monitoring::<ClientChen>(self.sender_to_gui.clone(), client_params);
-                    self.client_channels.insert(client.id, (packet_sender , ClientType::Web));
                 },
 
                 ClientType::Chat=> {
-                    client_type = ClientType::Chat;
-                    self.create_and_spawn_client_with_monitoring::<ClientChen>(self.sender_to_gui.clone(),client_params);
-                    self.client_channels.insert(client.id, (packet_sender , ClientType::Chat));
+                    self.create_and_spawn_client_with_monitoring::<ChatClientDanylo>(self.sender_to_gui.clone(), client_params);
                 }
             };
+            self.client_channels.insert(client.id, (packet_sender , chosen_type));
 
-            controller.register_client(client.id, command_sender, client_type);
+            controller.register_client(client.id, command_sender, chosen_type);
 
         }
     }
@@ -321,7 +344,7 @@ impl NetworkInitializer {
                 .cloned()
                 .collect();
             // From those we choose randomly one Brand and we use it
-            if let Some(&chosen_type) = min_usage_client_types.choose(&mut thread_rng()) {
+            if let Some(&chosen_type) = min_usage_client_types.choose(&mut self.rng) {
                 // Update usage count
                 if let Some(usage) = self.client_type_usage.get_mut(&chosen_type) {
                     *usage += 1;
@@ -394,6 +417,9 @@ impl NetworkInitializer {
     pub fn create_servers(
         &mut self,
         servers: Vec<Server>,
+        server_types: HashMap<NodeId, ServerType>,
+        max_offline_messages: HashMap<NodeId, usize>,
+        text_dirs: HashMap<NodeId, String>,
         controller: &mut SimulationController,
         server_events_sender: Sender<ServerEvent>,
         topology: HashMap<NodeId, Vec<NodeId>>,
@@ -410,28 +436,37 @@ impl NetworkInitializer {
             // Clone sender for server events
             let server_events_sender_clone = server_events_sender.clone();
 
-            //Choosing type
-            let server_type;
+            //Choosing type: the one from the config, otherwise a random one
+            let server_type = match server_types.get(&server.id) {
+                Some(&server_type) => server_type,
+                None if self.rng.gen::<u8>()%2 == 0 => ServerType::Communication,
+                None if text_server_used => ServerType::Media,
+                None => ServerType::Text,
+            };
+
             //Fast fix on many servers
             let mut server_instance_comm: Option<CommunicationServer> = None;
             let mut server_instance_text: Option<TextServer>= None;
             let mut server_instance_media: Option<MediaServer>= None;
 
-            if random::<u8>()%2 == 0{
-                server_type = ServerType::Communication;
-
-                server_instance_comm = Some(CommunicationServer::new(
-                    server.id,
-                    server_events_sender_clone,
-                    command_receiver,
-                    packet_receiver,
-                    HashMap::new(),
-                ));
+            //Text and media servers share the same files, so the media referenced in the texts exist
+            if vec_files.is_empty() && matches!(server_type, ServerType::Text | ServerType::Media) {
+                vec_files = content::choose_random_texts(&mut self.rng);
+            }
 
-            }else{
-                if text_server_used {
+            match server_type {
+                ServerType::Communication => {
+                    server_instance_comm = Some(CommunicationServer::new(
+                        server.id,
+                        max_offline_messages.get(&server.id).copied().unwrap_or(DEFAULT_MAX_OFFLINE_MESSAGES),
+                        server_events_sender_clone,
+                        command_receiver,
+                        packet_receiver,
+                        HashMap::new(),
+                    ));
+                }
+                ServerType::Media => {
                     let content = content::get_media(vec_files.clone());
-                    server_type = ServerType::Media;
 
                     server_instance_media = Some(MediaServer::new(
                         server.id,
@@ -441,23 +476,35 @@ impl NetworkInitializer {
                         packet_receiver,
                         HashMap::new(),
                     ));
-                }else{
-                    vec_files = content::choose_random_texts();
-                    server_type = ServerType::Text;
+                }
+                ServerType::Text => {
+                    text_server_used = true;
+
+                    //Files of the configured directory, otherwise the compiled-in texts
+                    let default_texts = vec_files.iter().map(|(_, file)| file.clone()).collect::<Vec<String>>();
+                    let content = match text_dirs.get(&server.id) {
+                        Some(directory) => FileCatalogue::from_directory(Path::new(directory)).unwrap_or_else(|e| {
+                            eprintln!("{}, server {} uses the default texts", e, server.id);
+                            FileCatalogue::from_texts(default_texts)
+                        }),
+                        None => FileCatalogue::from_texts(default_texts),
+                    };
 
                     server_instance_text = Some(TextServer::new(
                         server.id,
-                        vec_files.iter().map(|(_, file)| file.clone()).collect::<Vec<String>>(),
+                        content,
                         server_events_sender_clone,
                         command_receiver,
                         packet_receiver,
                         HashMap::new(),
-                   ));
+                    ));
                 }
-            };
+                ServerType::Undefined => {}
+            }
 
             controller.register_server(server.id, command_sender, server_type);
 
+            controller.packet_senders.insert(server.id, packet_sender.clone());
             self.server_channels.insert(server.id, (packet_sender, server_type));
 
             // Create and run server
@@ -499,9 +546,12 @@ impl NetworkInitializer {
         for (node_id, connected_nodes_ids) in cloned_topology.iter() {
             for &connected_node_id in connected_nodes_ids {
 
-                // Retrieve the Sender channel based on node type
+                // Retrieve the Sender channel based on node type (the config is already validated)
                 let node_type = self.get_type(node_id);
-                let sender = self.get_sender_for_node(connected_node_id).unwrap();
+                let Some(sender) = self.get_sender_for_node(connected_node_id) else {
+                    eprintln!("Sender channel not found for node {}!", connected_node_id);
+                    continue;
+                };
 
                 // Add the senders to the connected nodes
                 match node_type {
@@ -509,7 +559,7 @@ impl NetworkInitializer {
                     Some(NodeType::Client) => controller.add_sender(*node_id, NodeType::Client ,connected_node_id, sender),
                     Some(NodeType::Server) => controller.add_sender(*node_id, NodeType::Server , connected_node_id, sender),
 
-                    None => panic!("Sender channel not found for node {}!", *node_id),
+                    None => eprintln!("Node type not found for node {}!", *node_id),
                 };
             }
         }
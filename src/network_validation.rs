@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+
+use wg_2024::{
+    config::Config,
+    network::NodeId,
+    packet::NodeType,
+};
+
+///Every rule of the WG protocol that a configuration file can break
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopologyError {
+    DuplicateId(NodeId),
+    SelfLoop(NodeId),
+    UnknownNeighbour { node_id: NodeId, neighbour_id: NodeId },
+    NotBidirectional { node_id: NodeId, neighbour_id: NodeId },
+    ClientNotConnectedToDrone { client_id: NodeId, neighbour_id: NodeId },
+    ClientWrongDroneCount { client_id: NodeId, count: usize },
+    ServerNotConnectedToDrone { server_id: NodeId, neighbour_id: NodeId },
+    ServerTooFewDrones { server_id: NodeId, count: usize },
+    InvalidPdr { drone_id: NodeId, pdr: f32 },
+    Disconnected(Vec<NodeId>),
+}
+
+impl Display for TopologyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TopologyError::DuplicateId(id) => write!(f, "Node id {} is used more than once", id),
+            TopologyError::SelfLoop(id) => write!(f, "Node {} is connected to itself", id),
+            TopologyError::UnknownNeighbour { node_id, neighbour_id } =>
+                write!(f, "Node {} is connected to {}, which doesn't exist", node_id, neighbour_id),
+            TopologyError::NotBidirectional { node_id, neighbour_id } =>
+                write!(f, "Node {} is connected to {}, but not the other way around", node_id, neighbour_id),
+            TopologyError::ClientNotConnectedToDrone { client_id, neighbour_id } =>
+                write!(f, "Client {} is connected to {}, which is not a drone", client_id, neighbour_id),
+            TopologyError::ClientWrongDroneCount { client_id, count } =>
+                write!(f, "Client {} is connected to {} drones, it must be 1 or 2", client_id, count),
+            TopologyError::ServerNotConnectedToDrone { server_id, neighbour_id } =>
+                write!(f, "Server {} is connected to {}, which is not a drone", server_id, neighbour_id),
+            TopologyError::ServerTooFewDrones { server_id, count } =>
+                write!(f, "Server {} is connected to {} drones, it must be at least 2", server_id, count),
+            TopologyError::InvalidPdr { drone_id, pdr } =>
+                write!(f, "Drone {} has PDR {}, it must be in [0, 1]", drone_id, pdr),
+            TopologyError::Disconnected(ids) =>
+                write!(f, "Nodes {:?} can't be reached from the rest of the network", ids),
+        }
+    }
+}
+
+///Checks the whole configuration and returns all the violations found
+pub fn validate_config(config: &Config) -> Result<(), Vec<TopologyError>> {
+    let mut errors = Vec::new();
+
+    //Collecting ids and types
+    let mut node_types: HashMap<NodeId, NodeType> = HashMap::new();
+    let mut topology: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+
+    let nodes = config.drone.iter().map(|drone| (drone.id, NodeType::Drone, &drone.connected_node_ids))
+        .chain(config.client.iter().map(|client| (client.id, NodeType::Client, &client.connected_drone_ids)))
+        .chain(config.server.iter().map(|server| (server.id, NodeType::Server, &server.connected_drone_ids)));
+
+    for (id, node_type, neighbours) in nodes {
+        if node_types.insert(id, node_type).is_some() {
+            errors.push(TopologyError::DuplicateId(id));
+        }
+        topology.entry(id).or_default().extend(neighbours.iter().cloned());
+    }
+
+    //Checking every link
+    for (&id, neighbours) in &topology {
+        for &neighbour_id in neighbours {
+            if neighbour_id == id {
+                errors.push(TopologyError::SelfLoop(id));
+                continue;
+            }
+
+            let Some(neighbour_type) = node_types.get(&neighbour_id) else {
+                errors.push(TopologyError::UnknownNeighbour { node_id: id, neighbour_id });
+                continue;
+            };
+
+            let is_bidirectional = topology
+                .get(&neighbour_id)
+                .map_or(false, |other_neighbours| other_neighbours.contains(&id));
+            if !is_bidirectional {
+                errors.push(TopologyError::NotBidirectional { node_id: id, neighbour_id });
+            }
+
+            match node_types.get(&id) {
+                Some(NodeType::Client) if *neighbour_type != NodeType::Drone => {
+                    errors.push(TopologyError::ClientNotConnectedToDrone { client_id: id, neighbour_id });
+                }
+                Some(NodeType::Server) if *neighbour_type != NodeType::Drone => {
+                    errors.push(TopologyError::ServerNotConnectedToDrone { server_id: id, neighbour_id });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    //Checking the number of drones of clients and servers
+    for client in &config.client {
+        let count = client.connected_drone_ids.iter().collect::<HashSet<_>>().len();
+        if !(1..=2).contains(&count) {
+            errors.push(TopologyError::ClientWrongDroneCount { client_id: client.id, count });
+        }
+    }
+    for server in &config.server {
+        let count = server.connected_drone_ids.iter().collect::<HashSet<_>>().len();
+        if count < 2 {
+            errors.push(TopologyError::ServerTooFewDrones { server_id: server.id, count });
+        }
+    }
+
+    //Checking the PDRs
+    for drone in &config.drone {
+        if !(0.0..=1.0).contains(&drone.pdr) {
+            errors.push(TopologyError::InvalidPdr { drone_id: drone.id, pdr: drone.pdr });
+        }
+    }
+
+    //Checking the graph is connected
+    if let Some(&start) = topology.keys().min() {
+        let mut visited: HashSet<NodeId> = HashSet::from([start]);
+        let mut queue: VecDeque<NodeId> = VecDeque::from([start]);
+
+        while let Some(current) = queue.pop_front() {
+            for &neighbour_id in topology.get(&current).into_iter().flatten() {
+                if topology.contains_key(&neighbour_id) && visited.insert(neighbour_id) {
+                    queue.push_back(neighbour_id);
+                }
+            }
+        }
+
+        let mut unreachable: Vec<NodeId> = topology.keys().filter(|id| !visited.contains(id)).cloned().collect();
+        if !unreachable.is_empty() {
+            unreachable.sort();
+            errors.push(TopologyError::Disconnected(unreachable));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Nodes<'a> = &'a [(NodeId, &'a [NodeId])];
+
+    ///Builds the configuration through its TOML form, as it is read from the file
+    fn config(drones: &[(NodeId, &[NodeId], f32)], clients: Nodes, servers: Nodes) -> Config {
+        let mut data = String::new();
+        for (id, neighbours, pdr) in drones {
+            data += &format!("[[drone]]\nid = {}\nconnected_node_ids = {:?}\npdr = {:?}\n\n", id, neighbours, pdr);
+        }
+        for (id, neighbours) in clients {
+            data += &format!("[[client]]\nid = {}\nconnected_drone_ids = {:?}\n\n", id, neighbours);
+        }
+        for (id, neighbours) in servers {
+            data += &format!("[[server]]\nid = {}\nconnected_drone_ids = {:?}\n\n", id, neighbours);
+        }
+        toml::from_str(&data).unwrap()
+    }
+
+    fn assert_rejected(config: Config, expected: TopologyError) {
+        let errors = validate_config(&config).unwrap_err();
+        assert!(errors.contains(&expected), "{:?} not in {:?}", expected, errors);
+    }
+
+    #[test]
+    fn valid_config_is_accepted() {
+        let config = config(
+            &[(1, &[2, 3, 4], 0.1), (2, &[1, 4], 0.0)],
+            &[(3, &[1])],
+            &[(4, &[1, 2])],
+        );
+        assert_eq!(validate_config(&config), Ok(()));
+    }
+
+    #[test]
+    fn duplicate_id_is_rejected() {
+        let config = config(
+            &[(1, &[2, 3, 4], 0.1), (2, &[1, 4], 0.0)],
+            &[(3, &[1]), (1, &[2])],
+            &[(4, &[1, 2])],
+        );
+        assert_rejected(config, TopologyError::DuplicateId(1));
+    }
+
+    #[test]
+    fn self_loop_is_rejected() {
+        let config = config(
+            &[(1, &[1, 2, 3, 4], 0.1), (2, &[1, 4], 0.0)],
+            &[(3, &[1])],
+            &[(4, &[1, 2])],
+        );
+        assert_rejected(config, TopologyError::SelfLoop(1));
+    }
+
+    #[test]
+    fn unknown_neighbour_is_rejected() {
+        let config = config(
+            &[(1, &[2, 3, 4], 0.1), (2, &[1, 4, 9], 0.0)],
+            &[(3, &[1])],
+            &[(4, &[1, 2])],
+        );
+        assert_rejected(config, TopologyError::UnknownNeighbour { node_id: 2, neighbour_id: 9 });
+    }
+
+    #[test]
+    fn one_way_link_is_rejected() {
+        let config = config(
+            &[(1, &[2, 3, 4], 0.1), (2, &[1, 3, 4], 0.0)],
+            &[(3, &[1])],
+            &[(4, &[1, 2])],
+        );
+        assert_rejected(config, TopologyError::NotBidirectional { node_id: 2, neighbour_id: 3 });
+    }
+
+    #[test]
+    fn client_and_server_linked_directly_are_rejected() {
+        let config = || config(
+            &[(1, &[2, 3, 4], 0.1), (2, &[1, 4], 0.0)],
+            &[(3, &[1, 4])],
+            &[(4, &[1, 2, 3])],
+        );
+        assert_rejected(config(), TopologyError::ClientNotConnectedToDrone { client_id: 3, neighbour_id: 4 });
+        assert_rejected(config(), TopologyError::ServerNotConnectedToDrone { server_id: 4, neighbour_id: 3 });
+    }
+
+    #[test]
+    fn client_without_drones_is_rejected() {
+        let config = config(
+            &[(1, &[2, 3, 4], 0.1), (2, &[1, 4], 0.0)],
+            &[(3, &[1]), (5, &[])],
+            &[(4, &[1, 2])],
+        );
+        assert_rejected(config, TopologyError::ClientWrongDroneCount { client_id: 5, count: 0 });
+    }
+
+    #[test]
+    fn client_with_three_drones_is_rejected() {
+        let config = config(
+            &[(1, &[2, 3, 4, 5], 0.1), (2, &[1, 4, 3], 0.0), (5, &[1, 3], 0.2)],
+            &[(3, &[1, 2, 5])],
+            &[(4, &[1, 2])],
+        );
+        assert_rejected(config, TopologyError::ClientWrongDroneCount { client_id: 3, count: 3 });
+    }
+
+    #[test]
+    fn server_with_one_drone_is_rejected() {
+        let config = config(
+            &[(1, &[2, 3, 4], 0.1), (2, &[1], 0.0)],
+            &[(3, &[1])],
+            &[(4, &[1])],
+        );
+        assert_rejected(config, TopologyError::ServerTooFewDrones { server_id: 4, count: 1 });
+    }
+
+    #[test]
+    fn pdr_out_of_range_is_rejected() {
+        let config = config(
+            &[(1, &[2, 3, 4], 1.5), (2, &[1, 4], 0.0)],
+            &[(3, &[1])],
+            &[(4, &[1, 2])],
+        );
+        assert_rejected(config, TopologyError::InvalidPdr { drone_id: 1, pdr: 1.5 });
+    }
+
+    #[test]
+    fn disconnected_nodes_are_rejected() {
+        let config = config(
+            &[(1, &[2, 3, 4], 0.1), (2, &[1, 4], 0.0), (7, &[8], 0.0), (8, &[7], 0.0)],
+            &[(3, &[1])],
+            &[(4, &[1, 2])],
+        );
+        assert_rejected(config, TopologyError::Disconnected(vec![7, 8]));
+    }
+}
@@ -1,22 +1,25 @@
 use crossbeam_channel::{select_biased, Receiver, Sender};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Debug,
     future::Future,
+    time::Instant,
 };
 use tokio::{
     sync::mpsc,
     select,
 };
+use log::info;
 
 use wg_2024::{
     network::{NodeId},
     packet::{
+        NodeType,
         Packet,
         PacketType,
     },
 };
-use crate::general_use::{Message, Query, Response, ServerCommand, ServerEvent, ServerType};
+use crate::general_use::{ClientId, HistoryEntry, Message, MessageId, Query, Response, RoomName, ServerCommand, ServerEvent, ServerType};
 
 //UI
 use crate::ui_traits::{
@@ -26,10 +29,29 @@ use crate::ui_traits::{
 
 use super::server::CommunicationServer as CharTrait;
 use super::server::Server as MainTrait;
+use super::server::SendingMessage;
+use crate::fragmentation::Reassembler;
+use crate::id_allocator::IdAllocator;
+use crate::encoding::Encoding;
 
 type FloodId = u64;
 type SessionId = u64;
 
+///Messages kept for each offline client when the configuration doesn't give a limit
+pub const DEFAULT_MAX_OFFLINE_MESSAGES: usize = 100;
+
+///Messages kept for each conversation, the oldest are dropped first
+const MAX_HISTORY_MESSAGES: usize = 200;
+///Messages sent back for each Query::AskHistory
+const HISTORY_PAGE_SIZE: usize = 20;
+
+///Messages exchanged by two clients, numbered in the order the server received them
+#[derive(Debug, Default)]
+pub struct Conversation {
+    pub messages: VecDeque<HistoryEntry>,
+    pub next_seq: u64,
+}
+
 #[derive(Debug)]
 pub struct CommunicationServer{
 
@@ -37,15 +59,22 @@ pub struct CommunicationServer{
     pub id: NodeId,
 
     //Fragment-related
-    pub reassembling_messages: HashMap<SessionId, Vec<u8>>,
-    pub sending_messages: HashMap<SessionId, (Vec<u8>, NodeId)>,
+    pub reassembling_messages: HashMap<(NodeId, SessionId), Reassembler>,
+    pub completed_messages: HashMap<(NodeId, SessionId), Instant>,     // Messages already processed, with when they were completed
+    pub sending_messages: HashMap<SessionId, SendingMessage>,
+    pub pending_responses: HashMap<NodeId, Vec<(SessionId, Vec<u8>, Instant)>>, // Responses waiting for a route, with the time they were parked
+    pub encodings: HashMap<NodeId, Encoding>,                   // Encoding chosen by each client
+    pub deliveries: HashMap<SessionId, (NodeId, NodeId, MessageId, Message)>, // Forwarded messages not acked yet, with sender and recipient
+    pub relays: HashMap<SessionId, (NodeId, NodeId)>,           // Messages relayed to peer servers not acked yet, with sender and recipient
 
     //Flood-related
     pub clients: Vec<NodeId>,                                   // Available clients
     pub topology: HashMap<NodeId, Vec<NodeId>>,             // Nodes and their neighbours
-    pub routes: HashMap<NodeId, Vec<NodeId>>,                   // Routes to the servers
+    pub routes: HashMap<NodeId, Vec<NodeId>>,                   // Routes to the clients
+    pub node_types: HashMap<NodeId, NodeType>,                  // Types of the known nodes
     pub flood_ids: Vec<FloodId>,
-    pub counter: (FloodId, SessionId),
+    pub last_discovery: Option<(FloodId, Instant)>,             // Last flood we started, and when
+    pub ids: IdAllocator,                                       // Session and flood ids of this server
 
     //Channels
     pub to_controller_event: Sender<ServerEvent>,
@@ -55,11 +84,20 @@ pub struct CommunicationServer{
 
     //Characteristic-Server fields
     pub list_users: Vec<NodeId>,
+    pub offline_messages: HashMap<NodeId, VecDeque<(NodeId, MessageId, Message)>>, // Messages for the clients we can't reach, with their sender
+    pub max_offline_messages: usize,                            // Messages kept for each offline client
+    pub rooms: HashMap<RoomName, Vec<NodeId>>,                  // Chat rooms and their members
+    pub history: HashMap<(NodeId, NodeId), Conversation>,       // Conversations, by their two clients (smallest id first)
+
+    //Federation
+    pub peer_servers: HashMap<NodeId, Vec<ClientId>>,           // Other communication servers and their clients
+    pub contacted_servers: HashSet<NodeId>,                     // Servers we said hello to, or that said it to us
 }
 
 impl CommunicationServer{
     pub fn new(
         id: NodeId,
+        max_offline_messages: usize,
         to_controller_event: Sender<ServerEvent>,
         from_controller_command: Receiver<ServerCommand>,
         packet_recv: Receiver<Packet>,
@@ -69,13 +107,20 @@ impl CommunicationServer{
             id,
 
             reassembling_messages: Default::default(),
+            completed_messages: Default::default(),
             sending_messages: Default::default(),
+            pending_responses: Default::default(),
+            encodings: Default::default(),
+            deliveries: Default::default(),
+            relays: Default::default(),
 
             clients: Default::default(),                                   // Available clients
             topology: Default::default(),
             routes: Default::default(),
+            node_types: Default::default(),
             flood_ids: Default::default(),
-            counter: (0, 0),
+            last_discovery: None,
+            ids: IdAllocator::new(id),
 
             to_controller_event,
             from_controller_command,
@@ -83,6 +128,153 @@ impl CommunicationServer{
             packet_send,
 
             list_users: Vec::new(),
+            offline_messages: Default::default(),
+            max_offline_messages,
+            rooms: Default::default(),
+            history: Default::default(),
+
+            peer_servers: Default::default(),
+            contacted_servers: Default::default(),
+        }
+    }
+
+    fn deliver_message(&mut self, source_id: NodeId, destination_id: NodeId, message_id: MessageId, message: Message) {
+        let response = Response::MessageFrom(source_id, message_id, message.clone());
+
+        //Keeping the message until it's acked, so it can be stored if the client turns out offline.
+        //Without a route it is parked, and only goes to the offline queue if no route is found in time
+        let session_id = self.send_response(destination_id, response);
+        self.deliveries.insert(session_id, (source_id, destination_id, message_id, message));
+        self.send_event(ServerEvent::MessageForwarded { from: source_id, to: destination_id });
+    }
+
+    fn set_offline(&mut self, client_id: NodeId) {
+        if self.list_users.contains(&client_id) {
+            self.list_users.retain(|&id| id != client_id);
+            self.offline_messages.entry(client_id).or_default();
+            self.send_event(ServerEvent::ClientUnregistered(client_id));
+        }
+    }
+
+    ///Returns false if the queue of the client is full
+    fn store_offline_message(&mut self, source_id: NodeId, destination_id: NodeId, message_id: MessageId, message: Message) -> bool {
+        let queue = self.offline_messages.entry(destination_id).or_default();
+        if queue.len() >= self.max_offline_messages {
+            let error = format!("Client {} is offline and can't receive more messages", destination_id);
+            self.send_response(source_id, Response::Err(error));
+            return false;
+        }
+
+        queue.push_back((source_id, message_id, message));
+        self.send_response(source_id, Response::MessageQueued(destination_id, message_id));
+        true
+    }
+
+    fn record_history(&mut self, source_id: NodeId, destination_id: NodeId, message_id: MessageId, message: Message) {
+        let key = (source_id.min(destination_id), source_id.max(destination_id));
+        let conversation = self.history.entry(key).or_default();
+
+        conversation.messages.push_back(HistoryEntry { seq: conversation.next_seq, id: message_id, from: source_id, message });
+        conversation.next_seq += 1;
+        if conversation.messages.len() > MAX_HISTORY_MESSAGES {
+            conversation.messages.pop_front();
+        }
+    }
+
+    ///Clients a peer can send us messages for, the offline ones included
+    fn hosted_clients(&self) -> Vec<ClientId> {
+        let mut clients = self.list_users.clone();
+        clients.extend(self.offline_messages.keys().filter(|id| !self.list_users.contains(id)));
+        clients
+    }
+
+    fn peer_hosting(&self, client_id: ClientId) -> Option<NodeId> {
+        self.peer_servers
+            .iter()
+            .find(|(_, clients)| clients.contains(&client_id))
+            .map(|(&server_id, _)| server_id)
+    }
+
+    ///Queries to other servers are always in plain JSON, so servers of other groups can read them too
+    fn send_query(&mut self, server_id: NodeId, query: Query) -> SessionId {
+        let data = Encoding::Json.encode(&query).unwrap();
+        self.send_encoded(server_id, data)
+    }
+
+    ///Says hello to the servers we can reach and never talked to,
+    ///the communication servers among them answer with their clients
+    fn contact_new_servers(&mut self) {
+        let new_servers: Vec<NodeId> = self.node_types
+            .iter()
+            .filter(|&(&node_id, &node_type)| {
+                node_type == NodeType::Server && node_id != self.id && !self.contacted_servers.contains(&node_id)
+            })
+            .map(|(&node_id, _)| node_id)
+            .collect();
+
+        for server_id in new_servers {
+            if self.find_path_to(server_id).is_none() {
+                continue;
+            }
+            self.contacted_servers.insert(server_id);
+            let hello = Query::FederationHello(self.hosted_clients());
+            self.send_query(server_id, hello);
+        }
+    }
+
+    fn announce_clients(&mut self) {
+        let peers: Vec<NodeId> = self.peer_servers.keys().copied().collect();
+        for server_id in peers {
+            let announcement = Query::FederationClients(self.hosted_clients());
+            self.send_query(server_id, announcement);
+        }
+    }
+
+    fn welcome_peer(&mut self, server_id: NodeId, clients: Vec<ClientId>) {
+        self.peer_servers.insert(server_id, clients);
+        self.contacted_servers.insert(server_id);
+
+        let answer = Query::FederationClients(self.hosted_clients());
+        self.send_query(server_id, answer);
+    }
+
+    fn relay_message(&mut self, server_id: NodeId, source_id: NodeId, destination_id: NodeId, message_id: MessageId, message: Message) {
+        //The recipient answers the sender directly, the peer only tells if the relay failed
+        let session_id = self.send_query(server_id, Query::RelayMessage(source_id, destination_id, message_id, message.clone()));
+        self.relays.insert(session_id, (source_id, destination_id));
+        self.record_history(source_id, destination_id, message_id, message);
+    }
+
+    fn receive_relayed_message(&mut self, server_id: NodeId, source_id: NodeId, destination_id: NodeId, message_id: MessageId, message: Message) {
+        //Only the communication servers we know relay messages
+        if !self.peer_servers.contains_key(&server_id) {
+            return;
+        }
+
+        //Same as a message of one of our clients, the receipts go straight to the sender
+        if self.offline_messages.contains_key(&destination_id) {
+            if self.store_offline_message(source_id, destination_id, message_id, message.clone()) {
+                self.record_history(source_id, destination_id, message_id, message);
+            }
+            return;
+        }
+        if !self.list_users.contains(&destination_id) {
+            let error = format!("Client {} is not registered", destination_id);
+            self.send_response(source_id, Response::Err(error));
+            return;
+        }
+
+        self.record_history(source_id, destination_id, message_id, message.clone());
+        self.deliver_message(source_id, destination_id, message_id, message);
+    }
+
+    fn deliver_offline_messages(&mut self, client_id: NodeId) {
+        let Some(queue) = self.offline_messages.remove(&client_id) else {
+            return;
+        };
+
+        for (source_id, message_id, message) in queue {
+            self.deliver_message(source_id, client_id, message_id, message);
         }
     }
 }
@@ -105,6 +297,7 @@ impl Monitoring for CommunicationServer {
             let packet_crossbeam_rx = self.packet_recv.clone();
             tokio::spawn(crossbeam_to_tokio_bridge(packet_crossbeam_rx, packet_tokio_tx));
 
+            self.on_start();
             loop {
                 select! {
                     // Handle controller commands from the tokio mpsc channel
@@ -115,12 +308,12 @@ impl Monitoring for CommunicationServer {
                                     self.packet_send.insert(id, sender);
                                 }
                                 ServerCommand::RemoveSender(id) => {
-                                    self.packet_send.remove(&id);
+                                    self.remove_neighbour(id);
                                 }
                                 ServerCommand::ShortcutPacket(packet) => {
                                      match packet.pack_type {
                                         PacketType::Nack(nack) => self.handle_nack(nack, packet.session_id),
-                                        PacketType::Ack(ack) => self.handle_ack(ack),
+                                        PacketType::Ack(ack) => self.handle_ack(ack, packet.session_id),
                                         PacketType::MsgFragment(fragment) => self.handle_fragment(fragment, packet.routing_header ,packet.session_id),
                                         PacketType::FloodRequest(flood_request) => self.handle_flood_request(flood_request, packet.session_id),
                                         PacketType::FloodResponse(flood_response) => self.handle_flood_response(flood_response),
@@ -136,7 +329,7 @@ impl Monitoring for CommunicationServer {
                         if let Some(packet) = packet_res {
                             match packet.pack_type {
                                 PacketType::Nack(nack) => self.handle_nack(nack, packet.session_id),
-                                PacketType::Ack(ack) => self.handle_ack(ack),
+                                PacketType::Ack(ack) => self.handle_ack(ack, packet.session_id),
                                 PacketType::MsgFragment(fragment) => self.handle_fragment(fragment, packet.routing_header ,packet.session_id),
                                 PacketType::FloodRequest(flood_request) => self.handle_flood_request(flood_request, packet.session_id),
                                 PacketType::FloodResponse(flood_response) => self.handle_flood_response(flood_response),
@@ -148,6 +341,8 @@ impl Monitoring for CommunicationServer {
                     // Handle periodic tasks
                     _ = tokio::time::sleep(std::time::Duration::from_millis(10)) => {
                         // Perform periodic tasks here
+                        self.retransmit_due_fragments();
+                        self.handle_pending_responses();
                     },
                 }
             }
@@ -158,133 +353,343 @@ impl Monitoring for CommunicationServer {
 impl MainTrait for CommunicationServer{
     fn get_id(&self) -> NodeId{ self.id }
     fn get_server_type(&self) -> ServerType{ ServerType::Communication }
-
-    fn get_session_id(&mut self) -> u64{
-        self.counter.1 += 1;
-        self.counter.1
+    fn get_supported_queries(&self) -> Vec<&'static str>{
+        vec![
+            "AskType", "RegisterClient", "UnregisterClient", "AskListClients", "SendMessageTo", "MarkAsRead", "AskHistory",
+            "FederationHello", "FederationClients", "RelayMessage",
+            "AskListRooms", "CreateRoom", "JoinRoom", "LeaveRoom", "SendMessageToRoom",
+        ]
     }
 
-    fn get_flood_id(&mut self) -> u64{
-        self.counter.0 += 1;
-        self.counter.0
-    }
+    fn get_session_id(&mut self) -> u64{ self.ids.next_session_id() }
+    fn get_flood_id(&mut self) -> u64{ self.ids.next_flood_id() }
 
     fn push_flood_id(&mut self, flood_id: FloodId){ self.flood_ids.push(flood_id); }
     fn get_clients(&mut self) -> &mut Vec<NodeId>{ &mut self.clients }
     fn get_topology(&mut self) -> &mut HashMap<NodeId, Vec<NodeId>>{ &mut self.topology }
     fn get_routes(&mut self) -> &mut HashMap<NodeId, Vec<NodeId>>{ &mut self.routes }
+    fn get_node_types(&mut self) -> &mut HashMap<NodeId, NodeType>{ &mut self.node_types }
 
     fn get_from_controller_command(&mut self) -> &mut Receiver<ServerCommand>{ &mut self.from_controller_command }
     fn get_packet_recv(&mut self) -> &mut Receiver<Packet>{ &mut self.packet_recv }
     fn get_packet_send(&mut self) -> &mut HashMap<NodeId, Sender<Packet>>{ &mut self.packet_send }
     fn get_packet_send_not_mutable(&self) -> &HashMap<NodeId, Sender<Packet>>{ &self.packet_send }
-    fn get_reassembling_messages(&mut self) -> &mut HashMap<u64, Vec<u8>>{ &mut self.reassembling_messages }
-    fn get_sending_messages(&mut self) ->  &mut HashMap<u64, (Vec<u8>, u8)>{ &mut self.sending_messages }
-    fn get_sending_messages_not_mutable(&self) -> &HashMap<u64, (Vec<u8>, u8)>{ &self.sending_messages }
+    fn get_reassembling_messages(&mut self) -> &mut HashMap<(NodeId, SessionId), Reassembler>{ &mut self.reassembling_messages }
+    fn get_completed_messages(&mut self) -> &mut HashMap<(NodeId, SessionId), Instant>{ &mut self.completed_messages }
+    fn get_sending_messages(&mut self) ->  &mut HashMap<SessionId, SendingMessage>{ &mut self.sending_messages }
+    fn get_sending_messages_not_mutable(&self) -> &HashMap<SessionId, SendingMessage>{ &self.sending_messages }
+    fn get_pending_responses(&mut self) -> &mut HashMap<NodeId, Vec<(SessionId, Vec<u8>, Instant)>>{ &mut self.pending_responses }
+    fn get_last_discovery(&mut self) -> &mut Option<(FloodId, Instant)>{ &mut self.last_discovery }
+    fn get_encodings(&mut self) -> &mut HashMap<NodeId, Encoding>{ &mut self.encodings }
+    fn get_to_controller_event(&self) -> &Sender<ServerEvent>{ &self.to_controller_event }
+
+    fn on_start(&mut self){
+        //Flooding to find the other communication servers
+        self.discover();
+    }
+
+    fn on_message_delivered(&mut self, session_id: SessionId, _destination_id: NodeId){
+        self.relays.remove(&session_id);
+
+        //Confirming to the sender that its message arrived
+        if let Some((source_id, destination_id, message_id, _)) = self.deliveries.remove(&session_id) {
+            self.send_response(source_id, Response::MessageDelivered(destination_id, message_id));
+        }
+    }
+
+    fn on_message_abandoned(&mut self, session_id: SessionId, node_id: NodeId){
+        //A client we can't reach anymore is not online, its messages wait for it
+        self.set_offline(node_id);
+
+        //A peer we can't reach is forgotten, until we can say hello again
+        if self.peer_servers.remove(&node_id).is_some() {
+            self.contacted_servers.remove(&node_id);
+        }
+        if let Some((source_id, destination_id)) = self.relays.remove(&session_id) {
+            let error = format!("Message to {} could not be delivered", destination_id);
+            self.send_response(source_id, Response::Err(error));
+        }
+
+        if let Some((source_id, destination_id, message_id, message)) = self.deliveries.remove(&session_id) {
+            if self.offline_messages.contains_key(&destination_id) {
+                self.store_offline_message(source_id, destination_id, message_id, message);
+            } else {
+                let error = format!("Message to {} could not be delivered", destination_id);
+                self.send_response(source_id, Response::Err(error));
+            }
+        }
+    }
+
+    fn on_routes_updated(&mut self){
+        //Offline clients that can be reached again are back online and get their messages
+        let offline_clients: Vec<NodeId> = self.offline_messages.keys().copied().collect();
+        for client_id in offline_clients {
+            if self.find_path_to(client_id).is_none() {
+                continue;
+            }
+
+            if !self.list_users.contains(&client_id) {
+                self.list_users.push(client_id);
+                self.send_event(ServerEvent::ClientRegistered(client_id));
+            }
+            self.deliver_offline_messages(client_id);
+        }
+
+        self.contact_new_servers();
+    }
 
 
     fn process_reassembled_message(&mut self, data: Vec<u8>, src_id: NodeId){
-        match String::from_utf8(data.clone()) {
-            Ok(data_string) => match serde_json::from_str(&data_string) {
-                Ok(Query::AskType) => self.give_type_back(src_id),
-
-                Ok(Query::RegisterClient(node_id)) => self.add_client(node_id),
-                Ok(Query::AskListClients) => self.give_list_back(src_id),
-                Ok(Query::SendMessageTo(node_id, message)) => self.forward_message_to(node_id, message),
-                Err(_) => {
-                    panic!("Damn, not the right struct")
+        match self.decode_query(&data, src_id) {
+            Ok(Query::AskType) => self.give_type_back(src_id),
+
+            //A client can only register or unregister itself
+            Ok(Query::RegisterClient(node_id)) | Ok(Query::UnregisterClient(node_id)) if node_id != src_id => {
+                let error = format!("Client {} can't register or unregister client {}", src_id, node_id);
+                self.send_response(src_id, Response::Err(error));
+            }
+            Ok(Query::RegisterClient(_)) => self.add_client(src_id),
+            Ok(Query::UnregisterClient(_)) => self.remove_client(src_id),
+            Ok(Query::AskListClients) => self.give_list_back(src_id),
+            Ok(Query::SendMessageTo(node_id, message_id, message)) => self.forward_message_to(src_id, node_id, message_id, message),
+            Ok(Query::MarkAsRead(node_id, message_id)) => self.send_read_receipt(src_id, node_id, message_id),
+            Ok(Query::AskHistory(node_id, since)) => self.give_history_back(src_id, node_id, since),
+
+            //Only servers can be peers, a client could otherwise pretend to host anyone
+            Ok(query @ (Query::FederationHello(_) | Query::FederationClients(_) | Query::RelayMessage(..)))
+                if self.node_types.get(&src_id) != Some(&NodeType::Server) => {
+                let error = format!("{} is only accepted from servers", query.name());
+                self.send_response(src_id, Response::Err(error));
+            }
+            Ok(Query::FederationHello(clients)) => self.welcome_peer(src_id, clients),
+            Ok(Query::FederationClients(clients)) => {
+                self.peer_servers.insert(src_id, clients);
+                self.contacted_servers.insert(src_id);
+            }
+            Ok(Query::RelayMessage(source_id, destination_id, message_id, message)) =>
+                self.receive_relayed_message(src_id, source_id, destination_id, message_id, message),
+
+            Ok(Query::AskListRooms) => self.give_rooms_back(src_id),
+            Ok(Query::CreateRoom(room)) => self.create_room(src_id, room),
+            Ok(Query::JoinRoom(room)) => self.join_room(src_id, room),
+            Ok(Query::LeaveRoom(room)) => self.leave_room(src_id, room),
+            Ok(Query::SendMessageToRoom(room, message)) => self.send_message_to_room(src_id, room, message),
+            Ok(query) => {
+                //Query of another server type, or that we don't implement
+                let error = format!("{} is not supported by a {} server", query.name(), self.get_server_type());
+                self.send_response(src_id, Response::Err(error));
+            }
+            Err(e) => {
+                //Servers answering our hello with an error are not answered back, or it would never end
+                if self.node_types.get(&src_id) == Some(&NodeType::Server) {
+                    info!("Ignoring message of server {}: {}", src_id, e);
+                    return;
                 }
-                _ => {}
-            },
-                Err(e) => println!("Argh, {:?}", e),
+                self.send_response(src_id, Response::Err(format!("Malformed query: {}", e)));
             }
+        }
     }
 
 }
 
 impl CharTrait for CommunicationServer {
     fn add_client(&mut self, client_id: NodeId) {
-        self.list_users.push(client_id);
+        //Registering again is fine, the client is listed once
+        if !self.list_users.contains(&client_id) {
+            self.list_users.push(client_id);
+            self.send_event(ServerEvent::ClientRegistered(client_id));
+            self.announce_clients();
+        }
 
         let response = Response::ClientRegistered;
 
-        //Serializing message to send
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
+        self.send_response(client_id, response);
+
+        //Messages received while the client was offline
+        self.deliver_offline_messages(client_id);
+    }
 
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
+    fn remove_client(&mut self, client_id: NodeId) {
+        if self.list_users.contains(&client_id) {
+            self.list_users.retain(|&id| id != client_id);
+            self.send_event(ServerEvent::ClientUnregistered(client_id));
         }
 
-        //Generating header
-        let route: Vec<NodeId> = self.find_path_to(client_id);
-        let header = Self::create_source_routing(route);
+        //A client that leaves doesn't wait for messages anymore, and leaves its rooms
+        self.offline_messages.remove(&client_id);
+        self.announce_clients();
+        for members in self.rooms.values_mut() {
+            members.retain(|&id| id != client_id);
+        }
+        self.rooms.retain(|_, members| !members.is_empty());
 
-        // Generating ids
-        let session_id = self.generate_unique_session_id();
+        let response = Response::ClientUnregistered;
 
-        //Send fragments
-        self.send_fragments(session_id, n_fragments,response_in_vec_bytes, header);
+        self.send_response(client_id, response);
     }
 
     fn give_list_back(&mut self, client_id: NodeId) {
 
-        //Get list
-        let list_clients = self.list_users.clone();
+        //Get list, with the clients of the peer servers we can relay to
+        let mut list_clients = self.list_users.clone();
+        for client_id in self.peer_servers.values().flatten() {
+            if !list_clients.contains(client_id) {
+                list_clients.push(*client_id);
+            }
+        }
 
         //Creating data to send
         let response = Response::ListClients(list_clients);
 
-        //Serializing message to send
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
+        self.send_response(client_id, response);
+    }
+
+    fn forward_message_to(&mut self, source_id: NodeId, destination_id: NodeId, message_id: MessageId, message: Message) {
+
+        //Only registered clients can talk to each other
+        if !self.list_users.contains(&source_id) {
+            self.send_response(source_id, Response::Err("You are not registered".to_string()));
+            return;
+        }
+        //Registered clients we can't reach get the message later
+        if self.offline_messages.contains_key(&destination_id) {
+            if self.store_offline_message(source_id, destination_id, message_id, message.clone()) {
+                self.record_history(source_id, destination_id, message_id, message);
+            }
+            return;
+        }
+        if !self.list_users.contains(&destination_id) {
+            //Clients of a peer server get it through their server
+            if let Some(server_id) = self.peer_hosting(destination_id) {
+                self.relay_message(server_id, source_id, destination_id, message_id, message);
+                return;
+            }
+            let error = format!("Client {} is not registered", destination_id);
+            self.send_response(source_id, Response::Err(error));
+            return;
+        }
+
+        //Sending the message, the sender is the client who wrote to us
+        self.record_history(source_id, destination_id, message_id, message.clone());
+        self.deliver_message(source_id, destination_id, message_id, message);
+    }
 
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
+    fn send_read_receipt(&mut self, reader_id: NodeId, sender_id: NodeId, message_id: MessageId) {
+        if !self.list_users.contains(&reader_id) {
+            self.send_response(reader_id, Response::Err("You are not registered".to_string()));
+            return;
         }
+        //A sender that is gone doesn't wait for the receipt, the ones of peer servers get it directly
+        let is_known = self.list_users.contains(&sender_id)
+            || self.offline_messages.contains_key(&sender_id)
+            || self.peer_hosting(sender_id).is_some();
+        if !is_known {
+            return;
+        }
+
+        let response = Response::MessageRead(reader_id, message_id);
 
-        //Generating header
-        let route: Vec<NodeId> = self.find_path_to(client_id);
-        let header = Self::create_source_routing(route);
+        self.send_response(sender_id, response);
+    }
 
-        // Generating ids
-        let session_id = self.generate_unique_session_id();
+    fn give_history_back(&mut self, client_id: NodeId, peer_id: NodeId, since: u64) {
+        if !self.list_users.contains(&client_id) {
+            self.send_response(client_id, Response::Err("You are not registered".to_string()));
+            return;
+        }
 
-        //Send fragments
-        self.send_fragments(session_id, n_fragments,response_in_vec_bytes, header);
+        //One page of the messages from `since`, and where the next page starts if there is one
+        let key = (client_id.min(peer_id), client_id.max(peer_id));
+        let mut remaining = self.history
+            .get(&key)
+            .map(|conversation| conversation.messages.iter().filter(|entry| entry.seq >= since))
+            .into_iter()
+            .flatten();
+        let page: Vec<HistoryEntry> = remaining.by_ref().take(HISTORY_PAGE_SIZE).cloned().collect();
+        let next = remaining.next().map(|entry| entry.seq);
 
+        let response = Response::History(peer_id, page, next);
+
+        self.send_response(client_id, response);
     }
 
-    fn forward_message_to(&mut self, destination_id: NodeId, message: Message) {
+    fn give_rooms_back(&mut self, client_id: NodeId) {
+
+        //Get list
+        let mut list_rooms: Vec<RoomName> = self.rooms.keys().cloned().collect();
+        list_rooms.sort();
 
         //Creating data to send
-        let response = Response::MessageFrom(destination_id, message);
+        let response = Response::ListRooms(list_rooms);
+
+        self.send_response(client_id, response);
+    }
+
+    fn create_room(&mut self, client_id: NodeId, room: RoomName) {
+        if !self.list_users.contains(&client_id) {
+            self.send_response(client_id, Response::Err("You are not registered".to_string()));
+            return;
+        }
+        if room.trim().is_empty() || self.rooms.contains_key(&room) {
+            self.send_response(client_id, Response::Err(format!("Room \"{}\" can't be created", room)));
+            return;
+        }
 
-        //Serializing message to send
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
+        //The creator is the first member
+        self.rooms.insert(room.clone(), vec![client_id]);
+        self.send_response(client_id, Response::RoomJoined(room));
+    }
 
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
+    fn join_room(&mut self, client_id: NodeId, room: RoomName) {
+        if !self.list_users.contains(&client_id) {
+            self.send_response(client_id, Response::Err("You are not registered".to_string()));
+            return;
+        }
+        match self.rooms.get_mut(&room) {
+            Some(members) if !members.contains(&client_id) => members.push(client_id),
+            Some(_) => {}
+            None => {
+                self.send_response(client_id, Response::Err(format!("Room \"{}\" doesn't exist", room)));
+                return;
+            }
         }
+        self.send_response(client_id, Response::RoomJoined(room));
+    }
 
-        //Generating header
-        let route: Vec<NodeId> = self.find_path_to(destination_id);
-        let header = Self::create_source_routing(route);
+    fn leave_room(&mut self, client_id: NodeId, room: RoomName) {
+        if let Some(members) = self.rooms.get_mut(&room) {
+            members.retain(|&id| id != client_id);
 
-        // Generating fragment
-        let session_id = self.generate_unique_session_id();
+            //Nobody left to talk in it
+            if members.is_empty() {
+                self.rooms.remove(&room);
+            }
+        }
 
-        self.send_fragments(session_id, n_fragments,response_in_vec_bytes, header);
+        self.send_response(client_id, Response::RoomLeft(room));
+    }
+
+    fn send_message_to_room(&mut self, source_id: NodeId, room: RoomName, message: Message) {
+        let members = match self.rooms.get(&room) {
+            Some(members) if members.contains(&source_id) => members.clone(),
+            _ => {
+                self.send_response(source_id, Response::Err(format!("You are not in room \"{}\"", room)));
+                return;
+            }
+        };
+
+        //Everyone online gets it, the sender too as confirmation
+        for member_id in members {
+            if self.offline_messages.contains_key(&member_id) {
+                continue;
+            }
+
+            let response = Response::RoomMessageFrom(room.clone(), source_id, message.clone());
+            self.send_response(member_id, response);
+            if member_id != source_id {
+                self.send_event(ServerEvent::MessageForwarded { from: source_id, to: member_id });
+            }
+        }
     }
 }
 
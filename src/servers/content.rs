@@ -1,9 +1,9 @@
 use std::collections::HashMap;
-use rand::random;
+use rand::Rng;
 
 const N_FILES: usize = 6;
 
-pub fn choose_random_texts() -> Vec<(u8, String)>{
+pub fn choose_random_texts<R: Rng>(rng: &mut R) -> Vec<(u8, String)>{
     let trying_closures = |x:u8| {
         if x < 4 {
             return x+3
@@ -12,10 +12,10 @@ pub fn choose_random_texts() -> Vec<(u8, String)>{
         }
     };
 
-    let n_files = trying_closures(random::<u8>()%(N_FILES as u8));
+    let n_files = trying_closures(rng.gen::<u8>()%(N_FILES as u8));
 
     let mut vec_files:Vec<(u8, String)> = Vec::new();
-    if random::<u8>()%2 == 0{
+    if rng.gen::<u8>()%2 == 0{
         for i in 0..n_files{
             vec_files.push((i,TEXT[i as usize].to_string()));
         }
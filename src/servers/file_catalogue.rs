@@ -0,0 +1,205 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+use log::{info, warn};
+
+use crate::general_use::FileInfo;
+
+///Time between two checks of the directory for changes
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+///Files are asked with a u8 ID, the ones after the first 256 are not served
+const MAX_FILES: usize = u8::MAX as usize + 1;
+
+///A text file of the catalogue
+#[derive(Debug, Clone)]
+pub struct TextFile {
+    pub name: String,
+    pub content: String,
+}
+
+///Files served by a text server: the compiled-in texts, or the ones of a directory,
+///read again when a file is added, removed or modified.
+///A file keeps its ID across reloads, so an ID taken from an older list never gives another file.
+///New files get new IDs, and the ID of a removed file is never given again.
+#[derive(Debug)]
+pub struct FileCatalogue {
+    files: BTreeMap<u8, TextFile>,                          // By ID
+    ids: HashMap<String, u8>,                               // ID of every name ever served, removed files included
+    next_id: usize,                                         // Up to MAX_FILES, then new files can't be served
+    directory: Option<PathBuf>,
+    fingerprint: Vec<(String, u64, Option<SystemTime>)>,   // Name, size and modification time of the files of the directory
+    last_check: Instant,
+}
+
+impl FileCatalogue {
+    pub fn from_texts(texts: Vec<String>) -> Self {
+        let files = texts
+            .into_iter()
+            .enumerate()
+            .map(|(i, content)| TextFile { name: format!("text_{}.txt", i + 1), content })
+            .collect();
+
+        let mut catalogue = Self::empty(None, Vec::new());
+        catalogue.set_files(files);
+        catalogue
+    }
+
+    pub fn from_directory(directory: &Path) -> Result<Self, String> {
+        let fingerprint = Self::fingerprint(directory)?;
+        let files = Self::read_files(directory, &fingerprint);
+        info!("Loaded {} files from {}", files.len(), directory.display());
+
+        let mut catalogue = Self::empty(Some(directory.to_path_buf()), fingerprint);
+        catalogue.set_files(files);
+        Ok(catalogue)
+    }
+
+    fn empty(directory: Option<PathBuf>, fingerprint: Vec<(String, u64, Option<SystemTime>)>) -> Self {
+        FileCatalogue {
+            files: BTreeMap::new(),
+            ids: HashMap::new(),
+            next_id: 0,
+            directory,
+            fingerprint,
+            last_check: Instant::now(),
+        }
+    }
+
+    pub fn get(&self, file_id: u8) -> Option<&TextFile> {
+        self.files.get(&file_id)
+    }
+
+    pub fn list(&self) -> Vec<FileInfo> {
+        self.files
+            .iter()
+            .map(|(&id, file)| FileInfo { id, name: file.name.clone(), size: file.content.len() })
+            .collect()
+    }
+
+    ///Replaces the files, a name already seen keeps its ID and a new name gets the next one
+    fn set_files(&mut self, files: Vec<TextFile>) {
+        self.files.clear();
+        for file in files {
+            let id = match self.ids.get(&file.name) {
+                Some(&id) => id,
+                None if self.next_id < MAX_FILES => {
+                    let id = self.next_id as u8;
+                    self.next_id += 1;
+                    self.ids.insert(file.name.clone(), id);
+                    id
+                }
+                None => {
+                    warn!("No IDs left for {}, it is not served", file.name);
+                    continue;
+                }
+            };
+            self.files.insert(id, file);
+        }
+    }
+
+    ///Reads the directory again if its files changed, checking at most once every RELOAD_CHECK_INTERVAL.
+    ///If the directory can't be read anymore, the files we have are still served.
+    pub fn reload_if_changed(&mut self) {
+        let Some(directory) = self.directory.clone() else {
+            return;
+        };
+        if self.last_check.elapsed() < RELOAD_CHECK_INTERVAL {
+            return;
+        }
+        self.last_check = Instant::now();
+
+        match Self::fingerprint(&directory) {
+            Ok(fingerprint) if fingerprint != self.fingerprint => {
+                let files = Self::read_files(&directory, &fingerprint);
+                self.set_files(files);
+                self.fingerprint = fingerprint;
+                info!("Reloaded {} files from {}", self.files.len(), directory.display());
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Keeping the files already loaded: {}", e),
+        }
+    }
+
+    ///Visible files of the directory, sorted by name
+    fn fingerprint(directory: &Path) -> Result<Vec<(String, u64, Option<SystemTime>)>, String> {
+        let entries = fs::read_dir(directory)
+            .map_err(|e| format!("Unable to read directory {}: {}", directory.display(), e))?;
+
+        let mut fingerprint: Vec<(String, u64, Option<SystemTime>)> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+                let name = entry.file_name().into_string().ok().filter(|name| !name.starts_with('.'))?;
+                Some((name, metadata.len(), metadata.modified().ok()))
+            })
+            .collect();
+        fingerprint.sort_by(|a, b| a.0.cmp(&b.0));
+
+        if fingerprint.len() > MAX_FILES {
+            warn!("{} has {} files, only the first {} are served", directory.display(), fingerprint.len(), MAX_FILES);
+            fingerprint.truncate(MAX_FILES);
+        }
+        Ok(fingerprint)
+    }
+
+    ///Files that are not valid UTF-8 text are skipped
+    fn read_files(directory: &Path, fingerprint: &[(String, u64, Option<SystemTime>)]) -> Vec<TextFile> {
+        fingerprint
+            .iter()
+            .filter_map(|(name, _, _)| match fs::read_to_string(directory.join(name)) {
+                Ok(content) => Some(TextFile { name: name.clone(), content }),
+                Err(e) => {
+                    warn!("Skipping {}: {}", name, e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(name: &str) -> TextFile {
+        TextFile { name: name.to_string(), content: format!("content of {}", name) }
+    }
+
+    fn ids(catalogue: &FileCatalogue) -> Vec<(u8, String)> {
+        catalogue.list().into_iter().map(|info| (info.id, info.name)).collect()
+    }
+
+    #[test]
+    fn ids_survive_files_added_and_removed_before_them() {
+        let mut catalogue = FileCatalogue::from_texts(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        assert_eq!(ids(&catalogue), vec![(0, "text_1.txt".to_string()), (1, "text_2.txt".to_string()), (2, "text_3.txt".to_string())]);
+
+        catalogue.set_files(vec![text("0_first.txt"), text("text_2.txt"), text("text_3.txt")]);
+        assert_eq!(ids(&catalogue), vec![(1, "text_2.txt".to_string()), (2, "text_3.txt".to_string()), (3, "0_first.txt".to_string())]);
+        assert!(catalogue.get(0).is_none());
+        assert_eq!(catalogue.get(2).unwrap().name, "text_3.txt");
+    }
+
+    #[test]
+    fn removed_ids_are_never_given_to_other_files() {
+        let mut catalogue = FileCatalogue::from_texts(vec!["a".to_string()]);
+        catalogue.set_files(vec![text("other.txt")]);
+        assert_eq!(ids(&catalogue), vec![(1, "other.txt".to_string())]);
+
+        //The same file coming back gets its own ID again
+        catalogue.set_files(vec![text("other.txt"), text("text_1.txt")]);
+        assert_eq!(ids(&catalogue), vec![(0, "text_1.txt".to_string()), (1, "other.txt".to_string())]);
+    }
+
+    #[test]
+    fn files_are_not_served_once_ids_run_out() {
+        let mut catalogue = FileCatalogue::from_texts(Vec::new());
+        catalogue.set_files((0..MAX_FILES).map(|i| text(&format!("old_{}", i))).collect());
+        catalogue.set_files(vec![text("new")]);
+        assert!(catalogue.list().is_empty());
+    }
+}
@@ -1,11 +1,13 @@
 use crossbeam_channel::{Receiver, Sender};
 use std::collections::{HashMap};
 use std::fmt::Debug;
+use std::time::Instant;
 use std::future::Future;
 use tokio::select;
 use wg_2024::{
     network::{NodeId},
     packet::{
+        NodeType,
         Packet,
         PacketType,
     },
@@ -15,6 +17,10 @@ use crate::general_use::{Query, Response, ServerCommand, ServerEvent, ServerType
 use crate::ui_traits::{crossbeam_to_tokio_bridge, Monitoring};
 use super::server::MediaServer as CharTrait;
 use super::server::Server as MainTrait;
+use super::server::SendingMessage;
+use crate::fragmentation::Reassembler;
+use crate::id_allocator::IdAllocator;
+use crate::encoding::Encoding;
 
 type FloodId = u64;
 type SessionId = u64;
@@ -25,15 +31,20 @@ pub struct MediaServer{
     pub id: NodeId,
 
     //Fragment-related
-    pub reassembling_messages: HashMap<SessionId, Vec<u8>>,
-    pub sending_messages: HashMap<SessionId, (Vec<u8>, NodeId)>,
+    pub reassembling_messages: HashMap<(NodeId, SessionId), Reassembler>,
+    pub completed_messages: HashMap<(NodeId, SessionId), Instant>,     // Messages already processed, with when they were completed
+    pub sending_messages: HashMap<SessionId, SendingMessage>,
+    pub pending_responses: HashMap<NodeId, Vec<(SessionId, Vec<u8>, Instant)>>, // Responses waiting for a route, with the time they were parked
+    pub encodings: HashMap<NodeId, Encoding>,                   // Encoding chosen by each client
 
     //Flood-related
     pub clients: Vec<NodeId>,                                   // Available clients
     pub topology: HashMap<NodeId, Vec<NodeId>>,             // Nodes and their neighbours
-    pub routes: HashMap<NodeId, Vec<NodeId>>,                   // Routes to the servers
+    pub routes: HashMap<NodeId, Vec<NodeId>>,                   // Routes to the clients
+    pub node_types: HashMap<NodeId, NodeType>,                  // Types of the known nodes
     pub flood_ids: Vec<FloodId>,
-    pub counter: (FloodId, SessionId),
+    pub last_discovery: Option<(FloodId, Instant)>,             // Last flood we started, and when
+    pub ids: IdAllocator,                                       // Session and flood ids of this server
 
     //Channels
     pub to_controller_event: Sender<ServerEvent>,
@@ -58,13 +69,18 @@ impl MediaServer {
             id,
 
             reassembling_messages: Default::default(),
+            completed_messages: Default::default(),
             sending_messages: Default::default(),
+            pending_responses: Default::default(),
+            encodings: Default::default(),
 
             clients: Default::default(),                                   // Available clients
             topology: Default::default(),
             routes: Default::default(),
+            node_types: Default::default(),
             flood_ids: Default::default(),
-            counter: (0, 0),
+            last_discovery: None,
+            ids: IdAllocator::new(id),
 
             to_controller_event,
             from_controller_command,
@@ -104,12 +120,12 @@ impl Monitoring for MediaServer {
                                     self.packet_send.insert(id, sender);
                                 }
                                 ServerCommand::RemoveSender(id) => {
-                                    self.packet_send.remove(&id);
+                                    self.remove_neighbour(id);
                                 }
                                 ServerCommand::ShortcutPacket(packet) => {
                                      match packet.pack_type {
                                         PacketType::Nack(nack) => self.handle_nack(nack, packet.session_id),
-                                        PacketType::Ack(ack) => self.handle_ack(ack),
+                                        PacketType::Ack(ack) => self.handle_ack(ack, packet.session_id),
                                         PacketType::MsgFragment(fragment) => self.handle_fragment(fragment, packet.routing_header ,packet.session_id),
                                         PacketType::FloodRequest(flood_request) => self.handle_flood_request(flood_request, packet.session_id),
                                         PacketType::FloodResponse(flood_response) => self.handle_flood_response(flood_response),
@@ -136,6 +152,8 @@ impl Monitoring for MediaServer {
                     // Handle periodic tasks
                     _ = tokio::time::sleep(std::time::Duration::from_millis(10)) => {
                         // Perform periodic tasks here
+                        self.retransmit_due_fragments();
+                        self.handle_pending_responses();
                     },
                 }
             }
@@ -146,44 +164,45 @@ impl Monitoring for MediaServer {
 impl MainTrait for MediaServer{
     fn get_id(&self) -> NodeId{ self.id }
     fn get_server_type(&self) -> ServerType{ ServerType::Media }
+    fn get_supported_queries(&self) -> Vec<&'static str>{ vec!["AskType", "AskMedia"] }
 
-    fn get_session_id(&mut self) -> u64{
-        self.counter.1 += 1;
-        self.counter.1
-    }
-
-    fn get_flood_id(&mut self) -> u64{
-        self.counter.0 += 1;
-        self.counter.0
-    }
+    fn get_session_id(&mut self) -> u64{ self.ids.next_session_id() }
+    fn get_flood_id(&mut self) -> u64{ self.ids.next_flood_id() }
 
     fn push_flood_id(&mut self, flood_id: FloodId){ self.flood_ids.push(flood_id); }
     fn get_clients(&mut self) -> &mut Vec<NodeId>{ &mut self.clients }
     fn get_topology(&mut self) -> &mut HashMap<NodeId, Vec<NodeId>>{ &mut self.topology }
     fn get_routes(&mut self) -> &mut HashMap<NodeId, Vec<NodeId>>{ &mut self.routes }
+    fn get_node_types(&mut self) -> &mut HashMap<NodeId, NodeType>{ &mut self.node_types }
 
 
     fn get_from_controller_command(&mut self) -> &mut Receiver<ServerCommand>{ &mut self.from_controller_command }
     fn get_packet_recv(&mut self) -> &mut Receiver<Packet>{ &mut self.packet_recv }
     fn get_packet_send(&mut self) -> &mut HashMap<NodeId, Sender<Packet>>{ &mut self.packet_send }
     fn get_packet_send_not_mutable(&self) -> &HashMap<NodeId, Sender<Packet>>{ &self.packet_send }
-    fn get_reassembling_messages(&mut self) -> &mut HashMap<u64, Vec<u8>>{ &mut self.reassembling_messages }
-    fn get_sending_messages(&mut self) ->  &mut HashMap<u64, (Vec<u8>, u8)>{ &mut self.sending_messages }
-    fn get_sending_messages_not_mutable(&self) -> &HashMap<u64, (Vec<u8>, u8)>{ &self.sending_messages }
+    fn get_reassembling_messages(&mut self) -> &mut HashMap<(NodeId, SessionId), Reassembler>{ &mut self.reassembling_messages }
+    fn get_completed_messages(&mut self) -> &mut HashMap<(NodeId, SessionId), Instant>{ &mut self.completed_messages }
+    fn get_sending_messages(&mut self) ->  &mut HashMap<SessionId, SendingMessage>{ &mut self.sending_messages }
+    fn get_sending_messages_not_mutable(&self) -> &HashMap<SessionId, SendingMessage>{ &self.sending_messages }
+    fn get_pending_responses(&mut self) -> &mut HashMap<NodeId, Vec<(SessionId, Vec<u8>, Instant)>>{ &mut self.pending_responses }
+    fn get_last_discovery(&mut self) -> &mut Option<(FloodId, Instant)>{ &mut self.last_discovery }
+    fn get_encodings(&mut self) -> &mut HashMap<NodeId, Encoding>{ &mut self.encodings }
+    fn get_to_controller_event(&self) -> &Sender<ServerEvent>{ &self.to_controller_event }
 
 
     fn process_reassembled_message(&mut self, data: Vec<u8>, src_id: NodeId){
-        match String::from_utf8(data.clone()) {
-            Ok(data_string) => match serde_json::from_str(&data_string) {
-                Ok(Query::AskType) => self.give_type_back(src_id),
-
-                Ok(Query::AskMedia(reference)) => self.give_media_back(src_id, reference),
-                Err(_) => {
-                    panic!("Damn, not the right struct")
-                }
-                _ => {}
-            },
-            Err(e) => println!("Argh, {:?}", e),
+        match self.decode_query(&data, src_id) {
+            Ok(Query::AskType) => self.give_type_back(src_id),
+
+            Ok(Query::AskMedia(reference)) => self.give_media_back(src_id, reference),
+            Ok(query) => {
+                //Query of another server type, or that we don't implement
+                let error = format!("{} is not supported by a {} server", query.name(), self.get_server_type());
+                self.send_response(src_id, Response::Err(error));
+            }
+            Err(e) => {
+                self.send_response(src_id, Response::Err(format!("Malformed query: {}", e)));
+            }
         }
     }
 }
@@ -198,31 +217,12 @@ impl CharTrait for MediaServer{
         let response: Response;
         if let Some(media) = media {
             response = Response::Media(media.clone());
+            self.send_event(ServerEvent::FileServed { client: client_id, file: reference.clone() });
         }else{
             response = Response::Err("Media not found".to_string());
         }
 
-        //Serializing message to send
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
-
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
-        }
-
-        //Generating header
-        let route: Vec<NodeId> = self.find_path_to(client_id);
-        let header = Self::create_source_routing(route);
-
-        // Generating ids
-        let session_id = self.generate_unique_session_id();
-
-        //Send fragments
-        self.send_fragments(session_id, n_fragments,response_in_vec_bytes, header);
-
+        self.send_response(client_id, response);
     }
 }
 
@@ -5,3 +5,4 @@ pub mod communication_server;
 pub mod media_server;
 pub mod text_server;
 pub mod content;
+pub mod file_catalogue;
@@ -1,21 +1,128 @@
 //I am a god
 
 use crossbeam_channel::{select_biased, Receiver, Sender};
-use std::collections::{HashMap};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 use log::info;
 use wg_2024::{
     network::{NodeId, SourceRoutingHeader},
     packet::{
         Ack, FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet, PacketType,
+        FRAGMENT_DSIZE,
     },
 };
-use crate::general_use::{FloodId, Message, Response ,ServerCommand, ServerType};
+use crate::general_use::{FloodId, Message, MessageId, Query, Response, RoomName, ServerCapabilities, ServerCommand, ServerEvent, ServerType, SessionId, PROTOCOL_VERSION};
+use crate::fragmentation::{Fragmenter, Reassembler};
+use crate::encoding::Encoding;
+
+///Time after which a message whose fragments stopped arriving is dropped
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+///Biggest query accepted, bigger ones are dropped before allocating their buffer
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+
+///Fragments of a message travelling at the same time, the others leave as the acks arrive
+const SENDING_WINDOW: usize = 8;
+
+///Wait before sending again a dropped fragment, doubled at every drop of the same fragment
+const RETRANSMISSION_BASE_DELAY: Duration = Duration::from_millis(10);
+const MAX_RETRANSMISSION_DELAY: Duration = Duration::from_secs(2);
+const MAX_RETRANSMISSIONS: u32 = 20;
+
+///Wait before flooding again while some destinations are still unreachable
+const DISCOVERY_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+///Wait before flooding again only because some responses are still parked
+const PENDING_DISCOVERY_INTERVAL: Duration = Duration::from_secs(10);
+
+///Time a response waits for a route before being abandoned
+const PENDING_RESPONSE_TTL: Duration = Duration::from_secs(30);
+
+///Message being sent, kept until every fragment is acked
+#[derive(Debug)]
+pub struct SendingMessage {
+    pub fragments: Vec<Fragment>,                       // Built once, so every retransmission is identical
+    pub destination: NodeId,
+    pub acked: Vec<bool>,
+    pub n_acked: usize,
+    pub next_index: usize,                              // First fragment never sent
+    pub retransmissions: Vec<u32>,                      // Times each fragment has been sent again
+    pub scheduled: Vec<(u64, Instant)>,                 // Fragments waiting to be sent again
+}
+
+impl SendingMessage {
+    pub fn new(fragments: Vec<Fragment>, destination: NodeId) -> Self {
+        let n_fragments = fragments.len();
+        SendingMessage {
+            fragments,
+            destination,
+            acked: vec![false; n_fragments],
+            n_acked: 0,
+            next_index: 0,
+            retransmissions: vec![0; n_fragments],
+            scheduled: Vec::new(),
+        }
+    }
+
+    ///Next fragment to send for the first time, if the window allows it
+    pub fn next_to_send(&mut self) -> Option<u64> {
+        let in_flight = self.next_index - self.n_acked;
+        if self.next_index >= self.fragments.len() || in_flight >= SENDING_WINDOW {
+            return None;
+        }
+        self.next_index += 1;
+        Some((self.next_index - 1) as u64)
+    }
+
+    pub fn ack(&mut self, fragment_index: u64) {
+        if let Some(acked) = self.acked.get_mut(fragment_index as usize) {
+            if !*acked {
+                *acked = true;
+                self.n_acked += 1;
+            }
+        }
+        self.scheduled.retain(|(index, _)| *index != fragment_index);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.n_acked == self.fragments.len()
+    }
+
+    ///Schedules the fragment to be sent again later, returns false if it was sent again too many times
+    pub fn schedule_retransmission(&mut self, fragment_index: u64) -> bool {
+        let Some(retransmissions) = self.retransmissions.get_mut(fragment_index as usize) else {
+            return true;
+        };
+        if self.acked[fragment_index as usize] || self.scheduled.iter().any(|(index, _)| *index == fragment_index) {
+            return true;
+        }
+        if *retransmissions >= MAX_RETRANSMISSIONS {
+            return false;
+        }
+
+        let delay = RETRANSMISSION_BASE_DELAY
+            .saturating_mul(1 << (*retransmissions).min(16))
+            .min(MAX_RETRANSMISSION_DELAY);
+        *retransmissions += 1;
+        self.scheduled.push((fragment_index, Instant::now() + delay));
+        true
+    }
+
+    ///Removes and returns the fragments whose wait is over
+    pub fn take_due_retransmissions(&mut self) -> Vec<u64> {
+        let now = Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) = self.scheduled.drain(..).partition(|(_, time)| *time <= now);
+        self.scheduled = waiting;
+        due.into_iter().map(|(index, _)| index).collect()
+    }
+}
 
 
 ///SERVER TRAIT
 pub trait Server{
     fn get_id(&self) -> NodeId;
     fn get_server_type(&self) -> ServerType;
+    fn get_supported_queries(&self) -> Vec<&'static str>;
 
     fn get_session_id(&mut self) -> u64;
     fn get_flood_id(&mut self) -> u64;
@@ -24,17 +131,24 @@ pub trait Server{
     fn get_clients(&mut self) -> &mut Vec<NodeId>;
     fn get_topology(&mut self) -> &mut HashMap<NodeId, Vec<NodeId>>;
     fn get_routes(&mut self) -> &mut HashMap<NodeId, Vec<NodeId>>;
+    fn get_node_types(&mut self) -> &mut HashMap<NodeId, NodeType>;
 
     fn get_from_controller_command(&mut self) -> &mut Receiver<ServerCommand>;
     fn get_packet_recv(&mut self) -> &mut Receiver<Packet>;
     fn get_packet_send(&mut self) -> &mut HashMap<NodeId, Sender<Packet>>;
     fn get_packet_send_not_mutable(&self) -> &HashMap<NodeId, Sender<Packet>>;
 
-    fn get_reassembling_messages(&mut self) -> &mut HashMap<u64, Vec<u8>>;
-    fn get_sending_messages(&mut self) -> &mut HashMap<u64, (Vec<u8>, u8)>;
-    fn get_sending_messages_not_mutable(&self) -> &HashMap<u64, (Vec<u8>, u8)>;
+    fn get_reassembling_messages(&mut self) -> &mut HashMap<(NodeId, SessionId), Reassembler>;
+    fn get_completed_messages(&mut self) -> &mut HashMap<(NodeId, SessionId), Instant>;
+    fn get_sending_messages(&mut self) -> &mut HashMap<SessionId, SendingMessage>;
+    fn get_sending_messages_not_mutable(&self) -> &HashMap<SessionId, SendingMessage>;
+    fn get_pending_responses(&mut self) -> &mut HashMap<NodeId, Vec<(SessionId, Vec<u8>, Instant)>>;
+    fn get_last_discovery(&mut self) -> &mut Option<(FloodId, Instant)>;
+    fn get_encodings(&mut self) -> &mut HashMap<NodeId, Encoding>;
+    fn get_to_controller_event(&self) -> &Sender<ServerEvent>;
 
     fn run(&mut self) {
+        self.on_start();
         loop {
             select_biased! {
                 recv(self.get_from_controller_command()) -> command_res => {
@@ -45,12 +159,12 @@ pub trait Server{
 
                             }
                             ServerCommand::RemoveSender(id) => {
-                                self.get_packet_send().remove(&id);
+                                self.remove_neighbour(id);
                             }
                             ServerCommand::ShortcutPacket(packet) => {
                                  match packet.pack_type {
                                     PacketType::Nack(nack) => self.handle_nack(nack, packet.session_id),
-                                    PacketType::Ack(ack) => self.handle_ack(ack),
+                                    PacketType::Ack(ack) => self.handle_ack(ack, packet.session_id),
                                     PacketType::MsgFragment(fragment) => self.handle_fragment(fragment, packet.routing_header ,packet.session_id),
                                     PacketType::FloodRequest(flood_request) => self.handle_flood_request(flood_request, packet.session_id),
                                     PacketType::FloodResponse(flood_response) => self.handle_flood_response(flood_response),
@@ -63,35 +177,39 @@ pub trait Server{
                     if let Ok(packet) = packet_res {
                         match packet.pack_type {
                             PacketType::Nack(nack) => self.handle_nack(nack, packet.session_id),
-                            PacketType::Ack(ack) => self.handle_ack(ack),
+                            PacketType::Ack(ack) => self.handle_ack(ack, packet.session_id),
                             PacketType::MsgFragment(fragment) => self.handle_fragment(fragment, packet.routing_header ,packet.session_id),
                             PacketType::FloodRequest(flood_request) => self.handle_flood_request(flood_request, packet.session_id),
                             PacketType::FloodResponse(flood_response) => self.handle_flood_response(flood_response),
                         }
                     }
                 },
+                default(RETRANSMISSION_BASE_DELAY) => {},
             }
+
+            //Sending again the dropped fragments whose wait is over
+            self.retransmit_due_fragments();
+            self.handle_pending_responses();
+            self.on_tick();
         }
     }
 
     //FLOOD
+    ///Floods the network, the path traces that come back are merged into what we already know.
+    ///Nodes are only forgotten when reported as gone, so the routes in use keep working meanwhile.
     fn discover(&mut self) {
-
-        self.get_clients().clear();
-        self.get_routes().clear();
-        self.get_topology().clear();
-
         let flood_id = self.get_flood_id();
         self.push_flood_id(flood_id);
+        *self.get_last_discovery() = Some((flood_id, Instant::now()));
 
         // Create a new flood request initialized with the generated flood ID, the current node's ID, and its type.
         let flood_request = FloodRequest::initialize(
             flood_id,
             self.get_id(),
-            NodeType::Client,
+            NodeType::Server,
         );
 
-        // Generate a new session ID, incrementing the last one or starting at 1 if none exists.
+        // Generate a new session ID, unique among all the nodes.
         let session_id = self.get_session_id();
 
         // Create a new packet with the flood request and session ID.
@@ -102,8 +220,22 @@ pub trait Server{
         );
 
         // Attempt to send the flood request to all neighbors.
-        for (_, sender_channel) in self.get_packet_send_not_mutable() {
-            sender_channel.send(packet.clone()).expect("This error message shouldn't come out, (flooding error from server)");
+        for (neighbour_id, sender_channel) in self.get_packet_send_not_mutable() {
+            if sender_channel.send(packet.clone()).is_err() {
+                info!("Flood request not sent to {}", neighbour_id);
+            } else {
+                self.send_event(ServerEvent::PacketSent(packet.clone()));
+            }
+        }
+    }
+
+    ///Floods unless the last flood started less than DISCOVERY_RETRY_INTERVAL ago
+    fn request_discovery(&mut self) {
+        let running = self
+            .get_last_discovery()
+            .is_some_and(|(_, started)| started.elapsed() < DISCOVERY_RETRY_INTERVAL);
+        if !running {
+            self.discover();
         }
     }
 
@@ -112,6 +244,9 @@ pub trait Server{
         //Inserting self in flood request
         flood_request.increment(self.get_id(), NodeType::Server);
 
+        //The path trace is also a valid path from the initiator to us
+        self.update_topology_with_path(&flood_request.path_trace);
+
         //Creating and sending flood response
         let mut response = flood_request.generate_response(session_id);
         response.routing_header.increase_hop_index();
@@ -121,37 +256,127 @@ pub trait Server{
     }
 
     fn handle_flood_response(&mut self, flood_response: FloodResponse) {
-        println!("{:?}",flood_response);
+        info!("Handling flood response {:?}", flood_response);
+        self.update_topology_with_path(&flood_response.path_trace);
+    }
+
+    fn update_topology_with_path(&mut self, path_trace: &[(NodeId, NodeType)]) {
+        //Saving the type of every node of the path
+        for &(node_id, node_type) in path_trace {
+            self.get_node_types().insert(node_id, node_type);
+            if node_type == NodeType::Client && !self.get_clients().contains(&node_id) {
+                self.get_clients().push(node_id);
+            }
+        }
+
+        //Adding the links in both directions
+        for pair in path_trace.windows(2) {
+            let (first, second) = (pair[0].0, pair[1].0);
+
+            let neighbours = self.get_topology().entry(first).or_default();
+            if !neighbours.contains(&second) {
+                neighbours.push(second);
+            }
+
+            let neighbours = self.get_topology().entry(second).or_default();
+            if !neighbours.contains(&first) {
+                neighbours.push(first);
+            }
+        }
+
+        //New links may give shorter routes
+        self.update_routes();
+
+        //Some parked responses may have a route now
+        self.send_pending_responses();
+        self.on_routes_updated();
+    }
+
+    fn update_routes(&mut self) {
+        let clients = self.get_clients().clone();
+        self.get_routes().clear();
+
+        for client_id in clients {
+            if let Some(route) = self.compute_route(client_id) {
+                self.get_routes().insert(client_id, route);
+            }
+        }
+    }
+
+    ///Route on the known topology, see shortest_route
+    fn compute_route(&mut self, destination_id: NodeId) -> Option<Vec<NodeId>> {
+        let source_id = self.get_id();
+        let topology = self.get_topology().clone();
+        let node_types = self.get_node_types().clone();
+        shortest_route(source_id, destination_id, &topology, &node_types)
     }
 
     //NACK
     fn handle_nack(&mut self, nack: Nack, session_id: u64){
         match nack.nack_type {
             NackType::UnexpectedRecipient(_) => {
-                self.discover();
-                self.send_again_fragment(session_id, nack.fragment_index);
+                self.request_discovery();
+                self.schedule_retransmission(session_id, nack.fragment_index);
             },
             NackType::Dropped => {
-                self.send_again_fragment(session_id, nack.fragment_index);
+                //Waiting a bit more at every drop
+                self.schedule_retransmission(session_id, nack.fragment_index);
             },
             NackType::DestinationIsDrone => {
-                self.discover();
-                self.send_again_fragment(session_id, nack.fragment_index);
+                self.request_discovery();
+                self.schedule_retransmission(session_id, nack.fragment_index);
             },
-            NackType::ErrorInRouting(_) => {
-                self.discover();
+            NackType::ErrorInRouting(node_id) => {
+                //Routing around the node, or looking for new routes if there are none
+                self.remove_node_from_topology(node_id);
                 self.send_again_fragment(session_id, nack.fragment_index);
             }
         }
     }
+
+    fn remove_node_from_topology(&mut self, node_id: NodeId) {
+        self.get_topology().remove(&node_id);
+        for neighbours in self.get_topology().values_mut() {
+            neighbours.retain(|&id| id != node_id);
+        }
+        self.get_node_types().remove(&node_id);
+        self.update_routes();
+    }
+    ///Forgets the link to a neighbour, and the routes going through it
+    fn remove_neighbour(&mut self, neighbour_id: NodeId) {
+        self.get_packet_send().remove(&neighbour_id);
+
+        let id = self.get_id();
+        if let Some(neighbours) = self.get_topology().get_mut(&id) {
+            neighbours.retain(|&node_id| node_id != neighbour_id);
+        }
+        if let Some(neighbours) = self.get_topology().get_mut(&neighbour_id) {
+            neighbours.retain(|&node_id| node_id != id);
+        }
+        self.update_routes();
+    }
+
     fn send_nack(&self, nack: Nack, routing_header: SourceRoutingHeader, session_id: u64){
         let packet= Self::create_packet(PacketType::Nack(nack), routing_header, session_id);
         self.send_packet(packet);
     }
 
     //ACK
-    fn handle_ack(&mut self, _ack: Ack){
-        //UI stuff i guess?
+    fn handle_ack(&mut self, ack: Ack, session_id: u64){
+        let Some(sending_message) = self.get_sending_messages().get_mut(&session_id) else {
+            return;
+        };
+        sending_message.ack(ack.fragment_index);
+
+        //Freeing the session once everything arrived, otherwise the window moves on
+        if sending_message.is_complete() {
+            info!("All fragments of session {} acked", session_id);
+            let destination_id = sending_message.destination;
+            self.get_sending_messages().remove(&session_id);
+            self.on_message_delivered(session_id, destination_id);
+        } else {
+            self.send_window(session_id);
+        }
     }
     
     fn send_ack(&self, ack: Ack, routing_header: SourceRoutingHeader, session_id: u64) {
@@ -168,16 +393,42 @@ pub trait Server{
         }
     }
 
-    fn send_packet(&self, packet: Packet) {
-        let first_carrier = self
-            .get_packet_send_not_mutable()
-            .get(&packet.routing_header.hops[1])
-            .unwrap();
-        first_carrier.send(packet).unwrap();
+    fn send_packet(&self, packet: Packet) -> bool {
+        let Some(&next_hop) = packet.routing_header.hops.get(1) else {
+            info!("No next hop in {:?}", packet.routing_header);
+            return false;
+        };
+
+        let Some(first_carrier) = self.get_packet_send_not_mutable().get(&next_hop) else {
+            info!("No sender to neighbour {}", next_hop);
+            return false;
+        };
+
+        if first_carrier.send(packet.clone()).is_err() {
+            info!("Neighbour {} is unreachable", next_hop);
+            return false;
+        }
+
+        self.send_event(ServerEvent::PacketSent(packet));
+        true
+    }
+
+    //EVENTS
+    fn send_event(&self, event: ServerEvent) {
+        if self.get_to_controller_event().send(event).is_err() {
+            info!("Controller unreachable");
+        }
     }
 
-    fn find_path_to(&self, _destination_id: NodeId) -> Vec<NodeId>{
-        vec![4,2,1,3]
+    fn find_path_to(&mut self, destination_id: NodeId) -> Option<Vec<NodeId>>{
+        if let Some(route) = self.get_routes().get(&destination_id) {
+            return Some(route.clone());
+        }
+
+        //Not cached, trying with the current topology
+        let route = self.compute_route(destination_id)?;
+        self.get_routes().insert(destination_id, route.clone());
+        Some(route)
     }
 
     fn create_source_routing(route: Vec<NodeId>) -> SourceRoutingHeader{
@@ -191,7 +442,7 @@ pub trait Server{
 
     fn handle_fragment(&mut self, fragment: Fragment, routing_header: SourceRoutingHeader, session_id: u64, ){
         // Packet Verification
-        if self.get_id() != routing_header.hops[routing_header.hop_index] {
+        if routing_header.hops.get(routing_header.hop_index) != Some(&self.get_id()) {
             // Send Nack (UnexpectedRecipient)
             let nack = Nack {
                 fragment_index: fragment.fragment_index,
@@ -203,205 +454,357 @@ pub trait Server{
 
         info!("Handling Fragment {:?}", fragment);
 
-        //Getting vec of data from fragment
-        let mut data_to_add :Vec<u8> = fragment.data.to_vec();
-        data_to_add.truncate(fragment.length as usize);
-
-        //Fragment reassembly
-        // Check if it exists already
-        if let Some(reassembling_message) = self.get_reassembling_messages().get_mut(&session_id) {
-
-            // Check for valid fragment index and length
-            let offset = reassembling_message.len();
-            if offset + fragment.length as usize > reassembling_message.capacity()
-            {
-                println!("Nack");
-                //Maybe cancelling also message in reassembling_message
-                // ... error handling logic ...
-                return;
-
-            }else{
-                // Copy data to the correct offset in the vector.
-                reassembling_message.splice(((fragment.fragment_index*128) as usize)..((fragment.fragment_index*128) as usize), data_to_add);
-
-                // Check if all fragments have been received
-                let reassembling_message_clone = reassembling_message.clone();
-                println!("N fragments + current fragment length{}", fragment.total_n_fragments*128 + fragment.length as u64);
-                self.if_all_fragments_received_process(&reassembling_message_clone, &fragment, session_id, routing_header);
-            }
-
-        }else {
-            //Check if it is only 1 fragment
-            if !self.if_all_fragments_received_process(&data_to_add, &fragment, session_id, routing_header)
-            {
-                // New message, create a new entry in the HashMap.
-                let mut reassembling_message = Vec::with_capacity(fragment.total_n_fragments as usize * 128);
-
-                //Copying data
-                reassembling_message.splice(((fragment.fragment_index*128) as usize)..((fragment.fragment_index*128) as usize), data_to_add);
+        let Some(&source_id) = routing_header.hops.first() else {
+            return;
+        };
 
-                //Inserting message for future fragments
-                self.get_reassembling_messages()
-                    .insert(session_id, reassembling_message.clone());
-            }
+        //Refusing messages bigger than what we advertise
+        if fragment.total_n_fragments as usize > MAX_PAYLOAD_SIZE.div_ceil(FRAGMENT_DSIZE) {
+            info!("Message of session {} from {} is too big ({} fragments)", session_id, source_id, fragment.total_n_fragments);
+            return;
         }
-    }
 
-    fn if_all_fragments_received_process(&mut self, message: &Vec<u8>, current_fragment: &Fragment, session_id: u64, routing_header: SourceRoutingHeader) -> bool {
-        // Message Processing
+        //Forgetting the messages that the sender abandoned
+        self.remove_stale_messages();
 
-        info!("Message length {} n_fragments {} current.fragment length {}, fragment_index: {}", message.len(), current_fragment.total_n_fragments, current_fragment.length, current_fragment.fragment_index );
-        if(message.len() as u64) == ((current_fragment.total_n_fragments-1)*128 + current_fragment.length as u64)
-        {
-            let reassembled_data = message.clone(); // Take ownership of the data
-            self.get_reassembling_messages().remove(&session_id); // Remove from map
-            self.process_reassembled_message(reassembled_data, routing_header.hops[0]);
+        let key = (source_id, session_id);
+        let back_route: Vec<NodeId> = routing_header.hops.iter().rev().cloned().collect();
 
-            // Send Ack
+        //Late duplicates of a message already processed are only acked again
+        if self.get_completed_messages().contains_key(&key) {
+            info!("Fragment {} of session {} from {} belongs to a completed message", fragment.fragment_index, session_id, source_id);
             let ack = Ack {
-                fragment_index: current_fragment.fragment_index,
+                fragment_index: fragment.fragment_index,
             };
-            self.send_ack(ack, routing_header.get_reversed(), session_id);
-            return true;
+            self.send_ack(ack, Self::create_source_routing(back_route), session_id);
+            return;
         }
-        false
-    }
-
-    fn process_reassembled_message(&mut self, data: Vec<u8>, src_id: NodeId);
-
-    fn send_fragments(&mut self, session_id: u64, n_fragments: usize, response_in_vec_bytes: &[u8], header: SourceRoutingHeader) {
 
-        //Storing the all the fragments to send
-        self.get_sending_messages().insert(session_id, (response_in_vec_bytes.to_vec(), header.destination().unwrap()));
+        //Fragment reassembly
+        let (is_valid, message) = {
+            let reassembler = match self.get_reassembling_messages().entry(key) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match Reassembler::new(fragment.total_n_fragments) {
+                    Some(reassembler) => entry.insert(reassembler),
+                    None => return,
+                },
+            };
 
-        info!("Sending fragments n_fragments: {}", n_fragments);
-        //Sending
-        for i in 0..n_fragments{
+            let is_valid = reassembler.insert(&fragment);
+            (is_valid, reassembler.take_if_complete())
+        };
 
-            info!("Sending fragment fragment: {}", i);
-            //Preparing data of fragment
-            let mut data:[u8;128] = [0;128];
-            if(i+1)*128>response_in_vec_bytes.len(){
-                data[0..(response_in_vec_bytes.len()-(i*128))].copy_from_slice(&response_in_vec_bytes[i*128..response_in_vec_bytes.len()]);
+        if !is_valid {
+            info!("Fragment {} of session {} from {} is not valid", fragment.fragment_index, session_id, source_id);
+            return;
+        }
 
-            }else{
-                data.copy_from_slice(&response_in_vec_bytes[i*128..(1+i)*128]);
-            }
+        //Acking every fragment, also the duplicated ones since the previous ack may be lost
+        let ack = Ack {
+            fragment_index: fragment.fragment_index,
+        };
+        self.send_ack(ack, Self::create_source_routing(back_route), session_id);
+
+        //Processing the message once all the fragments are there
+        if let Some(message) = message {
+            self.get_reassembling_messages().remove(&key);
+            self.get_completed_messages().insert(key, Instant::now());
+            self.process_reassembled_message(message, source_id);
+        }
+    }
 
-            //Generating fragment
-            let fragment = Fragment::new(
-                i as u64,
-                n_fragments as u64,
-                data,
-            );
+    fn remove_stale_messages(&mut self) {
+        self.get_reassembling_messages()
+            .retain(|_, reassembler| reassembler.last_update().elapsed() < REASSEMBLY_TIMEOUT);
+        self.get_completed_messages()
+            .retain(|_, completed| completed.elapsed() < REASSEMBLY_TIMEOUT);
+    }
 
-            //Generating packet
-            let packet = Self::create_packet(
-                PacketType::MsgFragment(fragment),
-                header.clone(),
-                session_id,
-            );
+    fn process_reassembled_message(&mut self, data: Vec<u8>, src_id: NodeId);
 
-            self.send_packet(packet);
-        }
+    ///Reads the query, the responses to the client will use the same encoding
+    fn decode_query(&mut self, data: &[u8], src_id: NodeId) -> Result<Query, String> {
+        let (encoding, query) = Encoding::decode(data)?;
+        self.get_encodings().insert(src_id, encoding);
+        Ok(query)
     }
 
-    fn send_again_fragment(&mut self, session_id: u64, fragment_index: u64){
+    fn send_fragments(&mut self, session_id: u64, fragments: Vec<Fragment>, destination_id: NodeId) {
 
-        //Getting right message and destination id
-        let message_and_destination = self.get_sending_messages_not_mutable().get(&session_id).unwrap();
+        //Storing the all the fragments to send
+        self.get_sending_messages().insert(session_id, SendingMessage::new(fragments, destination_id));
 
-        //Preparing fields for Fragment
-        let length_response = message_and_destination.0.len();
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
-        }
+        //Sending the first ones
+        self.send_window(session_id);
+    }
 
-        let offset = fragment_index*128;
-        let mut data:[u8;128] = [0;128];
-        if offset > length_response as u64 {
-            data[0..(message_and_destination.0.len() as u64 - offset) as usize].copy_from_slice(&message_and_destination.0[offset as usize..message_and_destination.0.len()]);
-        }else{
-            data.copy_from_slice(&message_and_destination.0[offset as usize..(offset+128) as usize]);
+    fn send_window(&mut self, session_id: u64) {
+        while let Some(fragment_index) = self
+            .get_sending_messages()
+            .get_mut(&session_id)
+            .and_then(|sending_message| sending_message.next_to_send())
+        {
+            info!("Sending fragment {} of session {}", fragment_index, session_id);
+            self.send_again_fragment(session_id, fragment_index);
         }
+    }
 
-        //Generating fragment
-        let fragment = Fragment::new(
-            fragment_index,
-            n_fragments as u64,
-            data,
-        );
+    ///Sends the fragment on the current route, or schedules it for later if it can't leave now
+    fn send_again_fragment(&mut self, session_id: u64, fragment_index: u64){
+
+        //Getting right fragment and destination id
+        let Some((fragment, destination_id)) = self
+            .get_sending_messages_not_mutable()
+            .get(&session_id)
+            .and_then(|sending_message| Some((
+                sending_message.fragments.get(fragment_index as usize)?.clone(),
+                sending_message.destination,
+            )))
+        else {
+            return;
+        };
 
         //Finding route
-        let route = self.find_path_to(message_and_destination.1);
+        let Some(route) = self.find_path_to(destination_id) else {
+            info!("No route to {}", destination_id);
+            self.send_event(ServerEvent::RouteUnavailable(destination_id));
+            self.request_discovery();
+            self.schedule_retransmission(session_id, fragment_index);
+            return;
+        };
 
         //Generating packet
+        let next_hop = route.get(1).copied();
         let packet = Self::create_packet(
             PacketType::MsgFragment(fragment),
             Self::create_source_routing(route),
             session_id,
         );
-        self.send_packet(packet);
+        if !self.send_packet(packet) {
+            //The first link of the route doesn't work, the next try computes a route without it
+            if let Some(next_hop) = next_hop {
+                self.remove_neighbour(next_hop);
+            }
+            self.get_routes().remove(&destination_id);
+            self.schedule_retransmission(session_id, fragment_index);
+        }
+    }
 
+    fn schedule_retransmission(&mut self, session_id: u64, fragment_index: u64) {
+        let Some(sending_message) = self.get_sending_messages().get_mut(&session_id) else {
+            return;
+        };
+
+        if !sending_message.schedule_retransmission(fragment_index) {
+            let destination_id = sending_message.destination;
+            info!("Giving up on session {} to {}, fragment {} failed too many times", session_id, destination_id, fragment_index);
+            self.get_sending_messages().remove(&session_id);
+            self.send_event(ServerEvent::RouteUnavailable(destination_id));
+            self.on_message_abandoned(session_id, destination_id);
+        }
     }
-    
+
+    ///Called once, before handling any packet
+    fn on_start(&mut self) {}
+
+    ///Called regularly, between the packets
+    fn on_tick(&mut self) {}
+
+    ///Called after new links are learned, when some nodes may be reachable again
+    fn on_routes_updated(&mut self) {}
+
+    ///Called when every fragment of the session is acked by its destination
+    fn on_message_delivered(&mut self, _session_id: SessionId, _destination_id: NodeId) {}
+
+    ///Called when a message had to be abandoned, servers can forget what they keep about its destination
+    fn on_message_abandoned(&mut self, _session_id: SessionId, _destination_id: NodeId) {}
+
+    fn retransmit_due_fragments(&mut self) {
+        let due: Vec<(u64, u64)> = self
+            .get_sending_messages()
+            .iter_mut()
+            .flat_map(|(&session_id, sending_message)| {
+                sending_message
+                    .take_due_retransmissions()
+                    .into_iter()
+                    .map(move |fragment_index| (session_id, fragment_index))
+            })
+            .collect();
+
+        for (session_id, fragment_index) in due {
+            self.send_again_fragment(session_id, fragment_index);
+        }
+    }
+
     //Common functions
     fn give_type_back(&mut self, src_id: NodeId){
 
         info!("Sending back type back");
 
         //Get data
-        let response = Response::ServerType(self.get_server_type());
+        let capabilities = ServerCapabilities {
+            server_type: self.get_server_type(),
+            protocol_version: PROTOCOL_VERSION,
+            supported_queries: self.get_supported_queries().into_iter().map(String::from).collect(),
+            max_payload_size: MAX_PAYLOAD_SIZE,
+            encodings: vec![Encoding::Json, Encoding::MessagePack],
+        };
+        let response = Response::Capabilities(capabilities);
+
+        self.send_response(src_id, response);
+    }
 
-        //Serializing type
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
+    ///Sends the response now or once a route is found, returns the session that carries it
+    fn send_response(&mut self, client_id: NodeId, response: Response) -> SessionId {
 
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
-        }
+        //Serializing response, in the encoding chosen by the client (JSON if it never wrote to us)
+        let encoding = self.get_encodings().get(&client_id).copied().unwrap_or_default();
+        let response_in_vec_bytes = encoding.encode(&response).unwrap();
+
+        self.send_encoded(client_id, response_in_vec_bytes)
+    }
 
-        //Generating header
-        let route = self.find_path_to(src_id);
-        let header = Self::create_source_routing(route);
+    ///Sends an already serialized message now or once a route is found, returns the session that carries it
+    fn send_encoded(&mut self, destination_id: NodeId, data: Vec<u8>) -> SessionId {
 
         // Generating ids
-        let session_id = self.generate_unique_session_id();
+        let session_id = self.get_session_id();
+
+        //Waiting for the discovery if the destination can't be reached yet
+        if self.find_path_to(destination_id).is_none() {
+            info!("No route to {}, parking the response", destination_id);
+            self.send_event(ServerEvent::RouteUnavailable(destination_id));
+            self.get_pending_responses()
+                .entry(destination_id)
+                .or_default()
+                .push((session_id, data, Instant::now()));
+
+            self.request_discovery();
+            return session_id;
+        }
+
+        self.send_serialized_response(destination_id, session_id, &data);
+        session_id
+    }
+
+    fn send_serialized_response(&mut self, client_id: NodeId, session_id: SessionId, response_in_vec_bytes: &[u8]){
+
+        //Splitting in fragments
+        let fragments = Fragmenter::fragment(response_in_vec_bytes);
 
         //Send fragments
-        info!("Sending fragments");
-        self.send_fragments(session_id, n_fragments, response_in_vec_bytes, header);
+        info!("Sending {} fragments", fragments.len());
+        self.send_fragments(session_id, fragments, client_id);
     }
 
-    fn generate_unique_flood_id(&mut self) -> u64 {
-        let counter_flood_id = self.get_flood_id();
-        let id = self.get_id();
-        match format!("{}{}", id, counter_flood_id).parse() {
-            Ok(id) => id,
-            Err(e) => panic!("{}, Not right number", e)
+    fn send_pending_responses(&mut self){
+        let reachable_clients: Vec<NodeId> = self
+            .get_pending_responses()
+            .keys()
+            .cloned()
+            .collect::<Vec<NodeId>>()
+            .into_iter()
+            .filter(|&client_id| self.find_path_to(client_id).is_some())
+            .collect();
+
+        for client_id in reachable_clients {
+            let responses = self.get_pending_responses().remove(&client_id).unwrap_or_default();
+            info!("Flushing {} parked responses to {}", responses.len(), client_id);
+            for (session_id, response_in_vec_bytes, _) in responses {
+                self.send_serialized_response(client_id, session_id, &response_in_vec_bytes);
+            }
         }
     }
 
-    fn generate_unique_session_id(&mut self) -> u64 {
-        let counter_flood_id = self.get_flood_id();
-        let id = self.get_id();
-        match format!("{}{}", id, counter_flood_id).parse() {
-            Ok(id) => id,
-            Err(e) => panic!("{}, Not right number", e)
+    ///Abandons the responses that waited too long for a route, and floods again for the others
+    fn handle_pending_responses(&mut self){
+        let mut expired: Vec<(SessionId, NodeId)> = Vec::new();
+        for (&destination_id, responses) in self.get_pending_responses().iter_mut() {
+            responses.retain(|&(session_id, _, parked)| {
+                let is_expired = parked.elapsed() >= PENDING_RESPONSE_TTL;
+                if is_expired {
+                    expired.push((session_id, destination_id));
+                }
+                !is_expired
+            });
+        }
+        self.get_pending_responses().retain(|_, responses| !responses.is_empty());
+
+        for (session_id, destination_id) in expired {
+            info!("Response of session {} to {} waited too long for a route", session_id, destination_id);
+            self.on_message_abandoned(session_id, destination_id);
+        }
+
+        //The flood started when parking them may have been lost, trying again now and then
+        let flood_is_old = self
+            .get_last_discovery()
+            .is_none_or(|(_, started)| started.elapsed() >= PENDING_DISCOVERY_INTERVAL);
+        if !self.get_pending_responses().is_empty() && flood_is_old {
+            self.discover();
         }
     }
 }
 
 
+///BFS on the topology, only drones can be in the middle of a route
+fn shortest_route(
+    source_id: NodeId,
+    destination_id: NodeId,
+    topology: &HashMap<NodeId, Vec<NodeId>>,
+    node_types: &HashMap<NodeId, NodeType>,
+) -> Option<Vec<NodeId>> {
+    let mut queue: VecDeque<NodeId> = VecDeque::new();
+    let mut visited: HashSet<NodeId> = HashSet::new();
+    let mut predecessors: HashMap<NodeId, NodeId> = HashMap::new();
+
+    queue.push_back(source_id);
+    visited.insert(source_id);
+
+    while let Some(current) = queue.pop_front() {
+        if current == destination_id {
+            //Rebuilding the route going backwards
+            let mut route = vec![destination_id];
+            let mut node = destination_id;
+            while let Some(&previous) = predecessors.get(&node) {
+                route.push(previous);
+                node = previous;
+            }
+            route.reverse();
+            return Some(route);
+        }
+
+        //Clients and servers don't forward packets
+        if current != source_id && node_types.get(&current) != Some(&NodeType::Drone) {
+            continue;
+        }
+
+        if let Some(neighbours) = topology.get(&current) {
+            for &neighbour in neighbours {
+                if visited.insert(neighbour) {
+                    predecessors.insert(neighbour, current);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+    None
+}
+
 ///Communication Server functions
 pub trait CommunicationServer {
     fn add_client(&mut self, client_id: NodeId);
+    fn remove_client(&mut self, client_id: NodeId);
     fn give_list_back(&mut self, client_id: NodeId);
-    fn forward_message_to(&mut self, destination_id: NodeId, message: Message);
+    fn forward_message_to(&mut self, source_id: NodeId, destination_id: NodeId, message_id: MessageId, message: Message);
+    fn send_read_receipt(&mut self, reader_id: NodeId, sender_id: NodeId, message_id: MessageId);
+    fn give_history_back(&mut self, client_id: NodeId, peer_id: NodeId, since: u64);
+
+    //Rooms
+    fn give_rooms_back(&mut self, client_id: NodeId);
+    fn create_room(&mut self, client_id: NodeId, room: RoomName);
+    fn join_room(&mut self, client_id: NodeId, room: RoomName);
+    fn leave_room(&mut self, client_id: NodeId, room: RoomName);
+    fn send_message_to_room(&mut self, source_id: NodeId, room: RoomName, message: Message);
 }
 
 ///Content Server functions
@@ -415,3 +818,66 @@ pub trait MediaServer {
     fn give_media_back(&mut self, client_id: NodeId, reference: String);
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///Topology and types from undirected links
+    fn network(links: &[(NodeId, NodeId)], types: &[(NodeId, NodeType)]) -> (HashMap<NodeId, Vec<NodeId>>, HashMap<NodeId, NodeType>) {
+        let mut topology: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+        for &(first, second) in links {
+            topology.entry(first).or_default().push(second);
+            topology.entry(second).or_default().push(first);
+        }
+        (topology, types.iter().copied().collect())
+    }
+
+    #[test]
+    fn route_goes_through_drones() {
+        let (topology, node_types) = network(
+            &[(1, 10), (10, 11), (11, 2)],
+            &[(1, NodeType::Server), (10, NodeType::Drone), (11, NodeType::Drone), (2, NodeType::Client)],
+        );
+        assert_eq!(shortest_route(1, 2, &topology, &node_types), Some(vec![1, 10, 11, 2]));
+    }
+
+    #[test]
+    fn shortest_of_the_routes_is_chosen() {
+        let (topology, node_types) = network(
+            &[(1, 10), (10, 11), (11, 12), (12, 2), (1, 13), (13, 2)],
+            &[(1, NodeType::Server), (10, NodeType::Drone), (11, NodeType::Drone), (12, NodeType::Drone), (13, NodeType::Drone), (2, NodeType::Client)],
+        );
+        assert_eq!(shortest_route(1, 2, &topology, &node_types), Some(vec![1, 13, 2]));
+    }
+
+    #[test]
+    fn clients_and_servers_are_not_intermediate_hops() {
+        for middle_type in [NodeType::Client, NodeType::Server] {
+            let (topology, node_types) = network(
+                &[(1, 10), (10, 20), (20, 11), (11, 2)],
+                &[(1, NodeType::Server), (10, NodeType::Drone), (20, middle_type), (11, NodeType::Drone), (2, NodeType::Client)],
+            );
+            assert_eq!(shortest_route(1, 2, &topology, &node_types), None);
+        }
+    }
+
+    #[test]
+    fn blocked_node_is_avoided_when_a_drone_route_exists() {
+        let (topology, node_types) = network(
+            &[(1, 10), (10, 20), (20, 2), (10, 11), (11, 2)],
+            &[(1, NodeType::Server), (10, NodeType::Drone), (20, NodeType::Client), (11, NodeType::Drone), (2, NodeType::Client)],
+        );
+        assert_eq!(shortest_route(1, 2, &topology, &node_types), Some(vec![1, 10, 11, 2]));
+    }
+
+    #[test]
+    fn missing_destination_has_no_route() {
+        let (topology, node_types) = network(
+            &[(1, 10), (10, 2)],
+            &[(1, NodeType::Server), (10, NodeType::Drone), (2, NodeType::Client)],
+        );
+        assert_eq!(shortest_route(1, 3, &topology, &node_types), None);
+        assert_eq!(shortest_route(1, 3, &HashMap::new(), &HashMap::new()), None);
+    }
+}
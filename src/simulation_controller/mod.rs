@@ -1,3 +1,5 @@
 mod simulation_controller;
+mod scenario;
 
-pub use simulation_controller::*;
\ No newline at end of file
+pub use simulation_controller::*;
+pub use scenario::*;
\ No newline at end of file
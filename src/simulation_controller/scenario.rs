@@ -0,0 +1,123 @@
+use std::fs;
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use wg_2024::network::NodeId;
+
+use crate::general_use::{ClientId, DroneId, Message, ServerId};
+use crate::network_initializer::DroneBrand;
+use crate::encoding::Encoding;
+use super::SimulationController;
+
+///A list of timed actions executed against the simulation, loaded from a TOML file like:
+///
+/// ```toml
+/// [[step]]
+/// at = 2.0
+/// action = "crash_drone"
+/// drone = 5
+///
+/// [[step]]
+/// at = 5.0
+/// action = "send_message"
+/// client = 3
+/// server = 4
+/// to = 5
+/// message = "hi"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    #[serde(default, rename = "step")]
+    pub steps: Vec<ScenarioStep>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScenarioStep {
+    pub at: f64,                        // Seconds from the start of the scenario
+    #[serde(flatten)]
+    pub action: ScenarioAction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ScenarioAction {
+    CrashDrone { drone: DroneId },
+    SetPdr { drone: DroneId, pdr: f32 },
+    StartFlooding { client: ClientId },
+    SendMessage { client: ClientId, server: ServerId, to: NodeId, message: Message },
+    AddEdge { first: NodeId, second: NodeId },
+    RemoveEdge { first: NodeId, second: NodeId },
+    AddDrone { drone: DroneId, brand: DroneBrand, pdr: f32, neighbours: Vec<NodeId> },
+    SetEncoding { client: ClientId, encoding: Encoding },
+}
+
+impl Scenario {
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read scenario file {}: {}", path, e))?;
+        let mut scenario: Scenario = toml::from_str(&data)
+            .map_err(|e| format!("Failed to parse scenario file {}: {}", path, e))?;
+
+        if let Some(step) = scenario.steps.iter().find(|step| !step.at.is_finite() || step.at < 0.0) {
+            return Err(format!("Invalid time {} in scenario file {}", step.at, path));
+        }
+
+        // Steps are executed in time order, whatever their order in the file
+        scenario.steps.sort_by(|a, b| a.at.total_cmp(&b.at));
+        Ok(scenario)
+    }
+}
+
+impl SimulationController {
+    /// Schedules the steps of the scenario, starting from now. They are executed by `run` at their time.
+    pub fn load_scenario(&mut self, scenario: Scenario) {
+        let start = Instant::now();
+        self.scenario_steps = scenario.steps
+            .into_iter()
+            .map(|step| (start + Duration::from_secs_f64(step.at), step.action))
+            .collect();
+    }
+
+    pub(super) fn time_to_next_scenario_step(&self) -> Option<Duration> {
+        self.scenario_steps
+            .front()
+            .map(|(due, _)| due.saturating_duration_since(Instant::now()))
+    }
+
+    /// Executes the steps whose time has come.
+    /// A failing step is reported and doesn't stop the following ones.
+    pub(super) fn run_due_scenario_steps(&mut self) {
+        while let Some((due, _)) = self.scenario_steps.front() {
+            if *due > Instant::now() {
+                break;
+            }
+
+            let Some((_, action)) = self.scenario_steps.pop_front() else { break };
+            if let Err(e) = self.execute_scenario_action(action.clone()) {
+                eprintln!("Scenario step {:?} failed: {}", action, e);
+            }
+        }
+    }
+
+    fn execute_scenario_action(&mut self, action: ScenarioAction) -> Result<(), String> {
+        match action {
+            ScenarioAction::CrashDrone { drone } => self.request_drone_crash(drone),
+            ScenarioAction::SetPdr { drone, pdr } => {
+                if !self.command_senders_drones.contains_key(&drone) {
+                    return Err(format!("Drone {} not found in controller", drone));
+                }
+                if !(0.0..=1.0).contains(&pdr) {
+                    return Err(format!("PDR {} is not in [0, 1]", pdr));
+                }
+                self.set_packet_drop_rate(drone, pdr);
+                Ok(())
+            }
+            ScenarioAction::StartFlooding { client } => self.start_flooding_on_client(client),
+            ScenarioAction::SendMessage { client, server, to, message } =>
+                self.send_message_from_client(client, server, to, message),
+            ScenarioAction::AddEdge { first, second } => self.add_edge(first, second),
+            ScenarioAction::RemoveEdge { first, second } => self.remove_edge(first, second),
+            ScenarioAction::AddDrone { drone, brand, pdr, neighbours } => self.add_drone(drone, brand, pdr, neighbours),
+            ScenarioAction::SetEncoding { client, encoding } => self.set_client_encoding(client, encoding),
+        }
+    }
+}
@@ -1,20 +1,46 @@
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use std::collections::HashMap;
-use std::thread::sleep;
-use std::time::Duration;
+use crossbeam_channel::{select, unbounded, Receiver, Sender};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
 use wg_2024::{
     controller::{DroneCommand, DroneEvent},
     drone::Drone,
     network::NodeId,
     packet::{NodeType, Packet, PacketType}
 };
-use crate::general_use::{ClientCommand, ClientEvent,
+use crate::general_use::{ClientCommand, ClientEvent, DroneId,
                          ServerCommand, ServerEvent, ServerType, ClientType};
+use crate::network_initializer::DroneBrand;
+use crate::encoding::Encoding;
+use super::ScenarioAction;
+
+//Drones
+use rusty_drones::RustyDrone;
+use rolling_drone::RollingDrone;
+use rustable_drone::RustableDrone;
+use rustbusters_drone::RustBustersDrone;
+use rusteze_drone::RustezeDrone;
+use fungi_drone::FungiDrone;
+use bagel_bomber::BagelBomber;
+use skylink::SkyLinkDrone;
+use RF_drone::RustAndFurious;
+//use bobry_w_locie::drone::BoberDrone;
+
+/// Everything a drone needs to be created
+pub type DroneParams = (
+    DroneId,
+    Sender<DroneEvent>,
+    Receiver<DroneCommand>,
+    Receiver<Packet>,
+    HashMap<NodeId, Sender<Packet>>,
+    f32,
+);
 
 pub struct SimulationState {
     pub nodes: HashMap<NodeId, NodeType>,
     pub topology: HashMap<NodeId, Vec<NodeId>>,
     pub packet_history: Vec<PacketInfo>,
+    pub server_events: Vec<ServerEvent>,
 }
 
 
@@ -39,6 +65,8 @@ pub struct SimulationController {
     pub command_senders_clients: HashMap<NodeId, (Sender<ClientCommand>, ClientType)>,
     pub command_senders_servers: HashMap<NodeId, (Sender<ServerCommand>, ServerType)>,
     pub packet_senders: HashMap<NodeId, Sender<Packet>>,
+    pub packet_subscribers: Vec<Sender<PacketInfo>>,
+    pub scenario_steps: VecDeque<(Instant, ScenarioAction)>,
 }
 
 
@@ -56,6 +84,7 @@ impl SimulationController {
                 nodes: HashMap::new(),
                 topology: HashMap::new(),
                 packet_history: Vec::new(),
+                server_events: Vec::new(),
             },
             command_senders_drones: HashMap::new(),
             command_senders_clients: HashMap::new(),
@@ -67,33 +96,64 @@ impl SimulationController {
             server_event_sender,
             server_event_receiver,
             packet_senders: HashMap::new(),
+            packet_subscribers: Vec::new(),
+            scenario_steps: VecDeque::new(),
         }
     }
 
     /// Runs the main simulation loop.
-    /// This function continuously processes events, updates the GUI (not implemented), and sleeps briefly.
+    /// This function waits for the events of drones, clients and servers and handles each of them as soon
+    /// as it arrives, waking up in time to execute the steps of the loaded scenario.
     pub fn run(&mut self) {  // Note: &mut self since we're modifying state directly
         loop {
-            self.process_packet_sent_events();
-            self.process_packet_dropped_events();
-            self.process_controller_shortcut_events();
-            // GUI updates and user input...                                                            TODO
-            sleep(Duration::from_millis(100));
+            let timeout = self.time_to_next_scenario_step().unwrap_or(Duration::from_millis(100));
+
+            select! {
+                recv(self.drone_event_receiver) -> event_res => {
+                    if let Ok(event) = event_res {
+                        self.handle_drone_event(event);
+                    }
+                },
+                recv(self.client_event_receiver) -> event_res => {
+                    if let Ok(event) = event_res {
+                        self.handle_client_event(event);
+                    }
+                },
+                recv(self.server_event_receiver) -> event_res => {
+                    if let Ok(event) = event_res {
+                        self.handle_server_event(event);
+                    }
+                },
+                default(timeout) => {}
+            }
+
+            self.run_due_scenario_steps();
         }
     }
 
+    /// Returns a channel receiving every packet recorded from now on, as an alternative to polling `state.packet_history`.
+    /// The subscription ends when the receiver is dropped.
+    pub fn subscribe_to_packets(&mut self) -> Receiver<PacketInfo> {
+        let (sender, receiver) = unbounded();
+        self.packet_subscribers.push(sender);
+        receiver
+    }
+
     /// Registers a drone with the simulation controller.
     pub fn register_drone(&mut self, node_id: NodeId, command_sender: Sender<DroneCommand>) {
         self.command_senders_drones.insert(node_id, command_sender);
+        self.state.nodes.insert(node_id, NodeType::Drone);
     }
 
     pub fn register_server(&mut self, node_id: NodeId, command_sender: Sender<ServerCommand>, server_type: ServerType) {
 
         self.command_senders_servers.insert(node_id, (command_sender, server_type));
+        self.state.nodes.insert(node_id, NodeType::Server);
     }
 
     pub fn register_client(&mut self, node_id: NodeId, command_sender: Sender<ClientCommand>, client_type: ClientType) {
         self.command_senders_clients.insert(node_id, (command_sender, client_type));
+        self.state.nodes.insert(node_id, NodeType::Client);
     }
 
     /// Spawns a new drone.
@@ -117,61 +177,102 @@ impl SimulationController {
         Ok(drone)
     }
 
+    /// Creates the drone of the given brand and runs it in its own thread.
+    pub fn spawn_drone_of_brand(&mut self, brand: DroneBrand, drone_params: DroneParams) {
+        match brand {
+            DroneBrand::RustyDrone => self.spawn_drone::<RustyDrone>(drone_params),
+            DroneBrand::RollingDrones => self.spawn_drone::<RollingDrone>(drone_params),
+            DroneBrand::Rustable => self.spawn_drone::<RustableDrone>(drone_params),
+            //DroneBrand::RustBusters => self.spawn_drone::<RustBustersDrone>(drone_params),
+            DroneBrand::RustEze => self.spawn_drone::<RustezeDrone>(drone_params),
+            DroneBrand::Fungi => self.spawn_drone::<FungiDrone>(drone_params),
+            DroneBrand::BagelBomber => self.spawn_drone::<BagelBomber>(drone_params),
+            DroneBrand::SkyLink => self.spawn_drone::<SkyLinkDrone>(drone_params),
+            DroneBrand::RustAndFurious => self.spawn_drone::<RustAndFurious>(drone_params),
+            //DroneBrand::BobryWLucie => self.spawn_drone::<BoberDrone>(drone_params),
+        }
+    }
+
+    fn spawn_drone<T: Drone + Send + 'static>(&mut self, drone_params: DroneParams) {
+        let (drone_id, event_sender, cmd_receiver, pkt_receiver, pkt_senders, pdr) = drone_params;
+
+        let drone_instance = self.create_drone::<T>(
+            drone_id,
+            event_sender,
+            cmd_receiver,
+            pkt_receiver,
+            pkt_senders,
+            pdr,
+        );
+
+        thread::spawn(move || {
+            match drone_instance {
+                Ok(mut drone) => drone.run(),
+                Err(e) => panic!("Failed to run drone {}: {}", drone_id, e),
+            }
+        });
+    }
+
     /// Processes incoming events from drones.
     /// This function handles `PacketSent`, `PacketDropped`, and `ControllerShortcut` events.
-    fn process_packet_sent_events(&mut self) {
-        if let Ok(event) = self.drone_event_receiver.try_recv() {
-            if let DroneEvent::PacketSent(packet) = event {
-                self.handle_packet_sent(packet);
-            }
+    fn handle_drone_event(&mut self, event: DroneEvent) {
+        match event {
+            DroneEvent::PacketSent(packet) => self.handle_packet_sent(packet),
+            DroneEvent::PacketDropped(packet) => self.handle_packet_dropped(packet),
+            DroneEvent::ControllerShortcut(packet) => self.handle_controller_shortcut(packet),
         }
     }
 
-    fn process_packet_dropped_events(&mut self) {
-        if let Ok(event) = self.drone_event_receiver.try_recv() {
-            if let DroneEvent::PacketDropped(packet) = event {
-                self.handle_packet_dropped(packet);
-            }
+    /// Processes incoming events from clients.
+    fn handle_client_event(&mut self, event: ClientEvent) {
+        match event {
+            ClientEvent::PacketSent(packet) => self.handle_packet_sent(packet),
+            ClientEvent::KnownServers(servers) => self.update_known_servers(servers),
         }
     }
 
-    fn process_controller_shortcut_events(&mut self) {
-        if let Ok(event) = self.drone_event_receiver.try_recv() {  // Receive event or continue if none is available
-            if let DroneEvent::ControllerShortcut(packet) = event {   // Check event type
+    /// Processes incoming events from servers.
+    /// Sent packets go in the packet history like the ones of drones and clients, the other events in their own log.
+    fn handle_server_event(&mut self, event: ServerEvent) {
+        match event {
+            ServerEvent::PacketSent(packet) => self.handle_packet_sent(packet),
+            event => self.state.server_events.push(event),
+        }
+    }
 
-                match packet.pack_type {
-                    PacketType::Ack(_) | PacketType::Nack(_) | PacketType::FloodResponse(_) => {
-                        if let Some(destination) = self.get_destination_from_packet(&packet) {  // Try to get destination
+    /// Delivers the packets that drones couldn't route (Ack, Nack and FloodResponse) directly to their destination.
+    fn handle_controller_shortcut(&mut self, packet: Packet) {
+        match packet.pack_type {
+            PacketType::Ack(_) | PacketType::Nack(_) | PacketType::FloodResponse(_) => {
+                if let Some(destination) = self.get_destination_from_packet(&packet) {  // Try to get destination
 
-                            // Determine where to send the packet based on the destination ID and node type
-                            if self.command_senders_clients.contains_key(&destination) {          //If it's client
+                    // Determine where to send the packet based on the destination ID and node type
+                    if self.command_senders_clients.contains_key(&destination) {          //If it's client
 
-                                if let Some((client_sender, _)) = self.command_senders_clients.get(&destination) {
-                                    if let Err(e) = client_sender.send(ClientCommand::ShortcutPacket(packet.clone())) {
-                                        eprintln!("Error sending to client {}: {:?}", destination, e);
-                                    }
-                                } else {
+                        if let Some((client_sender, _)) = self.command_senders_clients.get(&destination) {
+                            if let Err(e) = client_sender.send(ClientCommand::ShortcutPacket(packet.clone())) {
+                                eprintln!("Error sending to client {}: {:?}", destination, e);
+                            }
+                        } else {
 
-                                    eprintln!("No sender found for client {}", destination);
-                                }
-                            } else if self.command_senders_servers.contains_key(&destination) {   // If it's server
-                                if let Some((server_sender, _)) = self.command_senders_servers.get(&destination) {
-                                    if let Err(e) = server_sender.send(ServerCommand::ShortcutPacket(packet.clone())) {
-                                        eprintln!("Error sending to server {}: {:?}", destination, e);
-                                    }
-                                } else {
-                                    eprintln!("No sender found for server {}", destination);
-                                }
-                            } else {
-                                eprintln!("Invalid destination or unknown node type: {}", destination);
+                            eprintln!("No sender found for client {}", destination);
+                        }
+                    } else if self.command_senders_servers.contains_key(&destination) {   // If it's server
+                        if let Some((server_sender, _)) = self.command_senders_servers.get(&destination) {
+                            if let Err(e) = server_sender.send(ServerCommand::ShortcutPacket(packet.clone())) {
+                                eprintln!("Error sending to server {}: {:?}", destination, e);
                             }
                         } else {
-                            eprintln!("Could not determine destination for ControllerShortcut");
+                            eprintln!("No sender found for server {}", destination);
                         }
+                    } else {
+                        eprintln!("Invalid destination or unknown node type: {}", destination);
                     }
-                    _ => eprintln!("Unexpected packet type in ControllerShortcut: {:?}", packet.pack_type),
+                } else {
+                    eprintln!("Could not determine destination for ControllerShortcut");
                 }
             }
+            _ => eprintln!("Unexpected packet type in ControllerShortcut: {:?}", packet.pack_type),
         }
     }
 
@@ -203,7 +304,7 @@ impl SimulationController {
     fn handle_packet_sent(&mut self, packet: Packet) {
         let destination = self.get_destination_from_packet(&packet).unwrap_or(255); // Provide default if None
 
-        self.state.packet_history.push(PacketInfo {
+        self.record_packet(PacketInfo {
             source: self.get_source_from_packet(&packet),
             destination,
             packet_type: packet.pack_type.clone(),
@@ -213,7 +314,7 @@ impl SimulationController {
 
     /// Handles `PacketDropped` events, adding packet information to the history.
     fn handle_packet_dropped(&mut self, packet: Packet) {
-        self.state.packet_history.push(PacketInfo {
+        self.record_packet(PacketInfo {
             source: self.get_source_from_packet(&packet),
             destination: self.get_destination_from_packet(&packet).unwrap_or(255), // 255 is a valid default
             packet_type: packet.pack_type.clone(),
@@ -221,6 +322,12 @@ impl SimulationController {
         });
     }
 
+    /// Adds the packet to the history and streams it to the subscribers, forgetting the ones that are gone.
+    fn record_packet(&mut self, packet_info: PacketInfo) {
+        self.packet_subscribers.retain(|subscriber| subscriber.send(packet_info.clone()).is_ok());
+        self.state.packet_history.push(packet_info);
+    }
+
     pub fn add_sender(&mut self, node_id: NodeId, node_type: NodeType, connected_node_id: NodeId, sender: Sender<Packet>) {
         match node_type {
             NodeType::Drone => {
@@ -305,17 +412,188 @@ impl SimulationController {
 
     /*- This function sends a Crash command to the specified drone_id.
 It uses the command_senders map to find the appropriate sender channel.
+The crash is refused if the network wouldn't be valid without the drone, otherwise
+the neighbours forget the drone and it is removed from the state.
 */
     pub fn request_drone_crash(&mut self, drone_id: NodeId) -> Result<(), String> {
-        if let Some(command_sender) = self.command_senders_drones.get(&drone_id) {
-            if let Err(e) = command_sender.send(DroneCommand::Crash) { // Error handling
-                eprintln!("Failed to send Crash command to drone {}: {:?}", drone_id, e);
-                return Err(format!("Failed to send Crash command to drone {}: {:?}", drone_id, e));
+        let Some(command_sender) = self.command_senders_drones.get(&drone_id) else {
+            return Err(format!("Drone {} not found in controller", drone_id));
+        };
+
+        self.check_crash_is_safe(drone_id)?;
+
+        if let Err(e) = command_sender.send(DroneCommand::Crash) { // Error handling
+            eprintln!("Failed to send Crash command to drone {}: {:?}", drone_id, e);
+            return Err(format!("Failed to send Crash command to drone {}: {:?}", drone_id, e));
+        }
+
+        // Neighbours stop sending to the drone, so it can empty its channel and stop
+        let neighbours = self.state.topology.remove(&drone_id).unwrap_or_default();
+        for neighbour_id in neighbours {
+            if let Some(neighbours_of_neighbour) = self.state.topology.get_mut(&neighbour_id) {
+                neighbours_of_neighbour.retain(|&id| id != drone_id);
             }
-            Ok(())
-        } else {
-            Err(format!("Drone {} not found in controller", drone_id))
+
+            let Some(&neighbour_type) = self.state.nodes.get(&neighbour_id) else {
+                eprintln!("Node type not found for node {}", neighbour_id);
+                continue;
+            };
+            if let Err(e) = self.remove_sender(neighbour_id, neighbour_type, drone_id) {
+                eprintln!("{}", e);
+            }
+        }
+
+        self.state.nodes.remove(&drone_id);
+        self.command_senders_drones.remove(&drone_id);
+        self.packet_senders.remove(&drone_id);
+        Ok(())
+    }
+
+    /// Checks that without the drone the network is still valid.
+    fn check_crash_is_safe(&self, drone_id: NodeId) -> Result<(), String> {
+        let mut nodes = self.state.nodes.clone();
+        nodes.remove(&drone_id);
+
+        let mut topology = self.state.topology.clone();
+        topology.remove(&drone_id);
+        for neighbours in topology.values_mut() {
+            neighbours.retain(|&id| id != drone_id);
+        }
+
+        check_network(&nodes, &topology)
+            .map_err(|e| format!("Crashing drone {} is not allowed: {}", drone_id, e))
+    }
+
+    /// Connects two nodes in both directions.
+    pub fn add_edge(&mut self, first_id: NodeId, second_id: NodeId) -> Result<(), String> {
+        let (first_type, second_type) = self.get_edge_types(first_id, second_id)?;
+
+        if self.state.topology.get(&first_id).map_or(false, |neighbours| neighbours.contains(&second_id)) {
+            return Err(format!("Nodes {} and {} are already connected", first_id, second_id));
+        }
+        if first_type != NodeType::Drone && second_type != NodeType::Drone {
+            return Err(format!("Nodes {} and {} can't be connected, one of them must be a drone", first_id, second_id));
+        }
+        for (client_id, client_type) in [(first_id, first_type), (second_id, second_type)] {
+            let n_neighbours = self.state.topology.get(&client_id).map_or(0, |neighbours| neighbours.len());
+            if client_type == NodeType::Client && n_neighbours >= 2 {
+                return Err(format!("Client {} is already connected to 2 drones", client_id));
+            }
+        }
+
+        let (Some(first_sender), Some(second_sender)) = (
+            self.packet_senders.get(&first_id).cloned(),
+            self.packet_senders.get(&second_id).cloned(),
+        ) else {
+            return Err(format!("Packet channel not found for node {} or {}", first_id, second_id));
+        };
+
+        self.add_sender(first_id, first_type, second_id, second_sender);
+        self.add_sender(second_id, second_type, first_id, first_sender);
+
+        self.state.topology.entry(first_id).or_default().push(second_id);
+        self.state.topology.entry(second_id).or_default().push(first_id);
+        Ok(())
+    }
+
+    /// Disconnects two nodes in both directions, if the network stays valid without the link.
+    pub fn remove_edge(&mut self, first_id: NodeId, second_id: NodeId) -> Result<(), String> {
+        let (first_type, second_type) = self.get_edge_types(first_id, second_id)?;
+
+        if !self.state.topology.get(&first_id).map_or(false, |neighbours| neighbours.contains(&second_id)) {
+            return Err(format!("Nodes {} and {} are not connected", first_id, second_id));
+        }
+
+        let mut topology = self.state.topology.clone();
+        for (node_id, other_id) in [(first_id, second_id), (second_id, first_id)] {
+            if let Some(neighbours) = topology.get_mut(&node_id) {
+                neighbours.retain(|&id| id != other_id);
+            }
+        }
+        check_network(&self.state.nodes, &topology)
+            .map_err(|e| format!("Removing the link between {} and {} is not allowed: {}", first_id, second_id, e))?;
+
+        self.remove_sender(first_id, first_type, second_id)?;
+        if let Err(e) = self.remove_sender(second_id, second_type, first_id) {
+            // Giving the link back to the first node, so both sides still agree
+            if let Some(second_sender) = self.packet_senders.get(&second_id).cloned() {
+                self.add_sender(first_id, first_type, second_id, second_sender);
+            }
+            return Err(e);
+        }
+
+        self.state.topology = topology;
+        Ok(())
+    }
+
+    fn get_edge_types(&self, first_id: NodeId, second_id: NodeId) -> Result<(NodeType, NodeType), String> {
+        if first_id == second_id {
+            return Err(format!("Node {} can't be connected to itself", first_id));
         }
+
+        let Some(&first_type) = self.state.nodes.get(&first_id) else {
+            return Err(format!("Node {} not found in controller", first_id));
+        };
+        let Some(&second_type) = self.state.nodes.get(&second_id) else {
+            return Err(format!("Node {} not found in controller", second_id));
+        };
+        Ok((first_type, second_type))
+    }
+
+    /// Spawns a new drone of the given brand while the simulation is running and connects it to the neighbours.
+    pub fn add_drone(&mut self, drone_id: NodeId, brand: DroneBrand, pdr: f32, neighbours: Vec<NodeId>) -> Result<(), String> {
+        if self.state.nodes.contains_key(&drone_id) {
+            return Err(format!("Node id {} is already used", drone_id));
+        }
+        if !(0.0..=1.0).contains(&pdr) {
+            return Err(format!("PDR {} is not in [0, 1]", pdr));
+        }
+        if neighbours.is_empty() {
+            return Err(format!("Drone {} must be connected to at least one node", drone_id));
+        }
+
+        // Checking all the links before touching the network
+        let mut seen_neighbours = HashSet::new();
+        for neighbour_id in &neighbours {
+            if *neighbour_id == drone_id {
+                return Err(format!("Node {} can't be connected to itself", drone_id));
+            }
+            if !seen_neighbours.insert(*neighbour_id) {
+                return Err(format!("Node {} appears more than once in the neighbours of drone {}", neighbour_id, drone_id));
+            }
+            let Some(&neighbour_type) = self.state.nodes.get(neighbour_id) else {
+                return Err(format!("Node {} not found in controller", neighbour_id));
+            };
+            if !self.packet_senders.contains_key(neighbour_id) {
+                return Err(format!("Packet channel not found for node {}", neighbour_id));
+            }
+            let n_neighbours = self.state.topology.get(neighbour_id).map_or(0, |neighbours| neighbours.len());
+            if neighbour_type == NodeType::Client && n_neighbours >= 2 {
+                return Err(format!("Client {} is already connected to 2 drones", neighbour_id));
+            }
+        }
+
+        let (command_sender, command_receiver) = unbounded();
+        let (packet_sender, packet_receiver) = unbounded();
+
+        self.register_drone(drone_id, command_sender);
+        self.packet_senders.insert(drone_id, packet_sender);
+        self.state.topology.insert(drone_id, Vec::new());
+
+        let drone_params = (
+            drone_id,
+            self.drone_event_sender.clone(),
+            command_receiver,
+            packet_receiver,
+            HashMap::new(),
+            pdr,
+        );
+        self.spawn_drone_of_brand(brand, drone_params);
+
+        for neighbour_id in neighbours {
+            self.add_edge(drone_id, neighbour_id)?;
+        }
+        Ok(())
     }
 
     pub fn run_client_ui(&self, client_id: NodeId) -> Result<(), String> {
@@ -430,6 +708,28 @@ It uses the command_senders map to find the appropriate sender channel.
         }
     }
 
+    pub fn send_message_from_client(&self, client_id: NodeId, server_id: NodeId, to: NodeId, message: String) -> Result<(), String> {
+        if let Some((client_command_sender, _)) = self.command_senders_clients.get(&client_id) {
+            if let Err(e) = client_command_sender.send(ClientCommand::SendMessageTo(server_id, to, message)) {
+                return Err(format!("Failed to send SendMessageTo command to client {}: {:?}", client_id, e));
+            }
+            Ok(())
+        } else {
+            Err(format!("Client with ID {} not found", client_id))
+        }
+    }
+
+    pub fn set_client_encoding(&self, client_id: NodeId, encoding: Encoding) -> Result<(), String> {
+        if let Some((client_command_sender, _)) = self.command_senders_clients.get(&client_id) {
+            if let Err(e) = client_command_sender.send(ClientCommand::SetEncoding(encoding)) {
+                return Err(format!("Failed to send SetEncoding command to client {}: {:?}", client_id, e));
+            }
+            Ok(())
+        } else {
+            Err(format!("Client with ID {} not found", client_id))
+        }
+    }
+
     pub fn ask_server_type_with_client_id(&mut self, client_id: NodeId, server_id: NodeId) -> Result<(), String> {
         if let Some((client_command_sender, _)) = self.command_senders_clients.get(&client_id) {
             if let Err(e) = client_command_sender.send(ClientCommand::AskTypeTo(server_id)) {
@@ -440,4 +740,47 @@ It uses the command_senders map to find the appropriate sender channel.
             Err(format!("Client with ID {} not found", client_id))
         }
     }
-}
\ No newline at end of file
+}
+
+/// Checks that every client and server has a drone as neighbour,
+/// and that all the nodes can reach each other passing only through drones.
+fn check_network(nodes: &HashMap<NodeId, NodeType>, topology: &HashMap<NodeId, Vec<NodeId>>) -> Result<(), String> {
+    let is_drone = |id: &NodeId| nodes.get(id) == Some(&NodeType::Drone);
+
+    // Every client and server must have at least one drone
+    for (&node_id, &node_type) in nodes {
+        if node_type != NodeType::Drone && !topology.get(&node_id).into_iter().flatten().any(is_drone) {
+            return Err(format!("{:?} {} would have no drones", node_type, node_id));
+        }
+    }
+
+    // The nodes must be connected, clients and servers can't be crossed
+    let Some(&start) = nodes.keys().filter(|id| is_drone(id)).min() else {
+        return if nodes.is_empty() {
+            Ok(())
+        } else {
+            Err("the network would have no drones".to_string())
+        };
+    };
+
+    let mut visited: HashSet<NodeId> = HashSet::from([start]);
+    let mut queue: VecDeque<NodeId> = VecDeque::from([start]);
+    while let Some(current) = queue.pop_front() {
+        if !is_drone(&current) {
+            continue;
+        }
+        for &neighbour_id in topology.get(&current).into_iter().flatten() {
+            if nodes.contains_key(&neighbour_id) && visited.insert(neighbour_id) {
+                queue.push_back(neighbour_id);
+            }
+        }
+    }
+
+    let mut unreachable: Vec<NodeId> = nodes.keys().filter(|id| !visited.contains(id)).cloned().collect();
+    if unreachable.is_empty() {
+        Ok(())
+    } else {
+        unreachable.sort();
+        Err(format!("nodes {:?} would be unreachable", unreachable))
+    }
+}